@@ -0,0 +1,54 @@
+//! Embeds a checkout of Thunderbird's ISPDB when the `bundled-ispdb` feature is enabled, see `Snapshot::bundled`.
+//!
+//! The ISPDB is not part of this crate, it is read from the directory in `AUTOCONFIG_ISPDB_DIR` or from an `ispdb` directory next to this file.
+
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=AUTOCONFIG_ISPDB_DIR");
+
+    if env::var_os("CARGO_FEATURE_BUNDLED_ISPDB").is_none() {
+        return;
+    }
+
+    let directory = match env::var_os("AUTOCONFIG_ISPDB_DIR") {
+        Some(directory) => PathBuf::from(directory),
+        None => PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("ispdb"),
+    };
+
+    println!("cargo:rerun-if-changed={}", directory.display());
+
+    let entries = fs::read_dir(&directory).unwrap_or_else(|error| {
+        panic!(
+            "The bundled-ispdb feature needs a checkout of the ispdb directory of https://github.com/thunderbird/autoconfig in {} or in AUTOCONFIG_ISPDB_DIR: {}",
+            directory.display(),
+            error
+        )
+    });
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            !path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(true, |name| name.starts_with('.'))
+        })
+        .collect();
+
+    // Sorted so that the order in which providers are added, and thus which one keeps shared servers, does not depend on the file system.
+    paths.sort();
+
+    let mut code = String::from("&[\n");
+
+    for path in paths {
+        code.push_str(&format!("    include_bytes!({:?}),\n", path));
+    }
+
+    code.push_str("]\n");
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("ispdb.rs");
+
+    fs::write(out, code).unwrap();
+}
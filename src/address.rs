@@ -0,0 +1,263 @@
+//! Parsing of email addresses as described in RFC 5321, including the internationalized addresses of RFC 6531.
+
+use std::{fmt, str::FromStr};
+
+use crate::error::{Error, ErrorKind, Result};
+
+const AT_SYMBOL: char = '@';
+
+/// The maximum length of a local part in octets.
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+/// The maximum length of a domain in its ASCII form, without a trailing dot.
+const MAX_DOMAIN_LENGTH: usize = 253;
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// The special characters that are allowed in an unquoted local part, besides letters and digits.
+const ATEXT_SPECIALS: &str = "!#$%&'*+-/=?^_`{|}~";
+
+/// An email address whose domain has been converted to its lowercase ASCII form, e.g. `müller@Bücher.de` has the domain `xn--bcher-kva.de`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EmailAddress {
+    local_part: String,
+    domain: String,
+}
+
+impl EmailAddress {
+    /// Parses an address, either with a dot separated local part like `first.last` or a quoted one like `"first last"`, which may contain any non-ASCII characters.
+    ///
+    /// Address literals like `user@[192.0.2.1]` are not accepted, as there is no domain to discover a config for.
+    pub fn parse<A: AsRef<str>>(address: A) -> Result<Self> {
+        let address = address.as_ref();
+
+        let (local_part, domain) = match address.rsplit_once(AT_SYMBOL) {
+            Some(parts) => parts,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::BadInput,
+                    "An email address must specify a domain after the '@' symbol",
+                ))
+            }
+        };
+
+        if !is_local_part(local_part) {
+            return Err(Error::new(
+                ErrorKind::BadInput,
+                "Given email address has an invalid local part",
+            ));
+        }
+
+        if domain.starts_with('[') {
+            return Err(Error::new(
+                ErrorKind::BadInput,
+                "Email addresses with an address literal instead of a domain are not supported",
+            ));
+        }
+
+        Ok(Self {
+            local_part: local_part.to_string(),
+            domain: domain_to_ascii(domain)?,
+        })
+    }
+
+    /// The part before the `@` symbol, exactly as it was given, including any quotes.
+    pub fn local_part(&self) -> &str {
+        &self.local_part
+    }
+
+    /// The domain in its lowercase ASCII form, which is what is used for DNS and http lookups.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// The domain in its Unicode form, for display purposes.
+    pub fn unicode_domain(&self) -> String {
+        idna::domain_to_unicode(&self.domain).0
+    }
+
+    /// Whether the local part contains non-ASCII characters, so sending mail to the address requires a server that supports SMTPUTF8.
+    pub fn is_internationalized(&self) -> bool {
+        !self.local_part.is_ascii()
+    }
+}
+
+impl FromStr for EmailAddress {
+    type Err = Error;
+
+    fn from_str(address: &str) -> Result<Self> {
+        Self::parse(address)
+    }
+}
+
+impl fmt::Display for EmailAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.local_part, AT_SYMBOL, self.domain)
+    }
+}
+
+/// Converts a domain to its lowercase ASCII form using IDNA, e.g. `Bücher.de` becomes `xn--bcher-kva.de`, and checks that it is a valid hostname.
+pub fn domain_to_ascii<D: AsRef<str>>(domain: D) -> Result<String> {
+    let ascii = hostname_to_ascii(domain)?;
+
+    // A domain that can receive mail has a top level domain, which is never numeric.
+    let valid_tld = match ascii.rsplit_once('.') {
+        Some((_, tld)) => !tld.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    };
+
+    if !valid_tld {
+        return Err(invalid_domain());
+    }
+
+    Ok(ascii)
+}
+
+/// Converts a hostname to its lowercase ASCII form like [`domain_to_ascii`], but also accepts names without a top level domain like `corp`, which only exist on internal networks.
+pub(crate) fn hostname_to_ascii<D: AsRef<str>>(domain: D) -> Result<String> {
+    let domain = domain.as_ref();
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+
+    let ascii = idna::domain_to_ascii(domain).map_err(|_| invalid_domain())?;
+
+    if ascii.is_empty() || ascii.len() > MAX_DOMAIN_LENGTH {
+        return Err(invalid_domain());
+    }
+
+    let valid_labels = ascii.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= MAX_LABEL_LENGTH
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+
+    if !valid_labels {
+        return Err(invalid_domain());
+    }
+
+    Ok(ascii)
+}
+
+/// Converts a domain to the form configs are indexed by, falling back to its lowercase form without a trailing dot if it is not a valid hostname.
+pub(crate) fn normalize_domain(domain: &str) -> String {
+    hostname_to_ascii(domain).unwrap_or_else(|_| domain.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// Converts a domain that is listed by a config to the form it is looked up in, keeping the `*.` prefix of a wildcard.
+pub(crate) fn listed_domain_to_ascii(listed: &str) -> String {
+    match listed.strip_prefix("*.") {
+        Some(domain) => format!("*.{}", normalize_domain(domain)),
+        None => normalize_domain(listed),
+    }
+}
+
+fn invalid_domain() -> Error {
+    Error::new(ErrorKind::BadInput, "Given domain is invalid")
+}
+
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ATEXT_SPECIALS.contains(c) || (!c.is_ascii() && !c.is_control())
+}
+
+/// Whether the local part is a dot separated string of atoms or a quoted string, as in RFC 5321 with the UTF-8 extension of RFC 6531.
+fn is_local_part(local_part: &str) -> bool {
+    if local_part.is_empty() || local_part.len() > MAX_LOCAL_PART_LENGTH {
+        return false;
+    }
+
+    match local_part
+        .strip_prefix('"')
+        .and_then(|quoted| quoted.strip_suffix('"'))
+    {
+        Some(content) => is_quoted_content(content),
+        None => local_part
+            .split('.')
+            .all(|atom| !atom.is_empty() && atom.chars().all(is_atext)),
+    }
+}
+
+fn is_quoted_content(content: &str) -> bool {
+    let mut chars = content.chars();
+
+    while let Some(c) = chars.next() {
+        let valid = match c {
+            // A quoted pair escapes any printable ASCII character.
+            '\\' => matches!(chars.next(), Some(' '..='~')),
+            '"' => false,
+            ' '..='~' => true,
+            c => !c.is_ascii() && !c.is_control(),
+        };
+
+        if !valid {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{listed_domain_to_ascii, EmailAddress};
+
+    #[test]
+    fn test_parse() {
+        let address = EmailAddress::parse("User.Name+tag@Example.COM").unwrap();
+
+        assert_eq!(address.local_part(), "User.Name+tag");
+        assert_eq!(address.domain(), "example.com");
+
+        assert_eq!(
+            EmailAddress::parse("info@studio.photography")
+                .unwrap()
+                .domain(),
+            "studio.photography"
+        );
+        assert_eq!(
+            EmailAddress::parse(r#""john \"doe\"@home"@example.com"#)
+                .unwrap()
+                .local_part(),
+            r#""john \"doe\"@home""#
+        );
+
+        let address = EmailAddress::parse("müller@Bücher.de").unwrap();
+
+        assert_eq!(address.domain(), "xn--bcher-kva.de");
+        assert_eq!(address.unicode_domain(), "bücher.de");
+        assert!(address.is_internationalized());
+        assert_eq!(address.to_string(), "müller@xn--bcher-kva.de");
+
+        assert!(EmailAddress::parse("用户@例子.广告").is_ok());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let long_local_part = format!("{}@example.com", "a".repeat(65));
+
+        for address in [
+            "example.com",
+            "user@",
+            "@example.com",
+            ".user@example.com",
+            "first..last@example.com",
+            "first last@example.com",
+            "\"unterminated@example.com",
+            "user@localhost",
+            "user@[192.0.2.1]",
+            "user@-example.com",
+            "user@example..com",
+            "user@example.123",
+            "user@exa_mple.com",
+            &long_local_part,
+        ] {
+            assert!(EmailAddress::parse(address).is_err(), "{}", address);
+        }
+    }
+
+    #[test]
+    fn test_listed_domain_to_ascii() {
+        assert_eq!(listed_domain_to_ascii("Bücher.de"), "xn--bcher-kva.de");
+        assert_eq!(listed_domain_to_ascii("*.bücher.de"), "*.xn--bcher-kva.de");
+        assert_eq!(listed_domain_to_ascii("CORP"), "corp");
+        assert_eq!(listed_domain_to_ascii("not a domain"), "not a domain");
+    }
+}
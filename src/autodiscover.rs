@@ -0,0 +1,367 @@
+//! Support for Microsoft's Autodiscover "Plain Old XML" protocol, which is used by Exchange and Office 365.
+//!
+//! See: https://learn.microsoft.com/en-us/exchange/client-developer/web-service-reference/pox-autodiscover-web-service-reference-for-exchange
+
+use std::io;
+
+use serde::Deserialize;
+
+use crate::{
+    config::{
+        AuthenticationType, Config, EmailProvider, EmailProviderProperty, SecurityType, Server,
+        ServerProperty, ServerType,
+    },
+    error::{Error, ErrorKind, Result},
+    utils::escape_xml,
+};
+
+const REQUEST_SCHEMA: &str =
+    "http://schemas.microsoft.com/exchange/autodiscover/outlook/requestschema/2006";
+
+const RESPONSE_SCHEMA: &str =
+    "http://schemas.microsoft.com/exchange/autodiscover/outlook/responseschema/2006a";
+
+const EMAIL_ADDRESS_PLACEHOLDER: &str = "%EMAILADDRESS%";
+const EMAIL_LOCALPART_PLACEHOLDER: &str = "%EMAILLOCALPART%";
+
+/// The urls at which an Autodiscover service can be found for a given domain.
+pub fn urls<D: AsRef<str>>(domain: D) -> Vec<String> {
+    vec![
+        format!(
+            "https://autodiscover.{}/autodiscover/autodiscover.xml",
+            domain.as_ref()
+        ),
+        format!("https://{}/autodiscover/autodiscover.xml", domain.as_ref()),
+    ]
+}
+
+/// Creates the XML body that has to be sent to an Autodiscover server.
+pub fn request_body<E: AsRef<str>>(email_address: E) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<Autodiscover xmlns="{}">
+    <Request>
+        <EMailAddress>{}</EMailAddress>
+        <AcceptableResponseSchema>{}</AcceptableResponseSchema>
+    </Request>
+</Autodiscover>"#,
+        REQUEST_SCHEMA,
+        escape_xml(email_address.as_ref()),
+        RESPONSE_SCHEMA
+    )
+}
+
+/// What an Autodiscover server told us to do.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// The server returned the settings for the account.
+    Settings(Config),
+    /// The request should be repeated at a different url.
+    RedirectUrl(String),
+    /// The request should be repeated using a different email address.
+    RedirectAddr(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Autodiscover {
+    response: Response,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Response {
+    account: Option<Account>,
+    error: Option<ResponseError>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResponseError {
+    error_code: Option<String>,
+    message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Account {
+    action: Option<String>,
+    redirect_url: Option<String>,
+    redirect_addr: Option<String>,
+    error: Option<ResponseError>,
+    #[serde(rename = "Protocol", default)]
+    protocols: Vec<Protocol>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Protocol {
+    r#type: String,
+    server: Option<String>,
+    port: Option<u16>,
+    login_name: Option<String>,
+    #[serde(rename = "SPA")]
+    spa: Option<String>,
+    #[serde(rename = "SSL")]
+    ssl: Option<String>,
+    encryption: Option<String>,
+    ews_url: Option<String>,
+    #[serde(rename = "OWAUrl")]
+    owa_url: Option<String>,
+}
+
+impl Protocol {
+    fn server_type(&self) -> Option<ServerType> {
+        match self.r#type.to_ascii_uppercase().as_str() {
+            "IMAP" => Some(ServerType::Imap),
+            "POP3" => Some(ServerType::Pop3),
+            "SMTP" => Some(ServerType::Smtp),
+            "EXCH" | "EXPR" => Some(ServerType::Exchange),
+            _ => None,
+        }
+    }
+
+    /// Follows Thunderbird's interpretation: the `Encryption` element takes precedence over `SSL`, where `TLS` means STARTTLS.
+    fn security_type(&self) -> SecurityType {
+        if let Some(encryption) = &self.encryption {
+            match encryption.to_ascii_lowercase().as_str() {
+                "none" => return SecurityType::Plain,
+                "tls" => return SecurityType::Starttls,
+                "ssl" => return SecurityType::Tls,
+                _ => {}
+            }
+        }
+
+        match self.ssl.as_deref() {
+            Some(ssl) if ssl.eq_ignore_ascii_case("off") => SecurityType::Plain,
+            _ => SecurityType::Tls,
+        }
+    }
+
+    fn username(&self, email_address: &str) -> String {
+        let local_part = email_address.split('@').next().unwrap_or_default();
+
+        match self.login_name.as_deref() {
+            Some(login) if login.eq_ignore_ascii_case(email_address) => {
+                EMAIL_ADDRESS_PLACEHOLDER.to_string()
+            }
+            Some(login) if login.eq_ignore_ascii_case(local_part) => {
+                EMAIL_LOCALPART_PLACEHOLDER.to_string()
+            }
+            Some(login) => login.to_string(),
+            None => EMAIL_ADDRESS_PLACEHOLDER.to_string(),
+        }
+    }
+
+    fn into_server(self, server_type: ServerType, email_address: &str) -> Option<Server> {
+        let hostname = self.server.clone()?;
+
+        let mut properties = vec![ServerProperty::Hostname(hostname)];
+
+        if let Some(port) = self.port {
+            properties.push(ServerProperty::Port(port));
+        }
+
+        properties.push(ServerProperty::SocketType(self.security_type()));
+        properties.push(ServerProperty::Username(self.username(email_address)));
+
+        let authentication = match self.spa.as_deref() {
+            Some(spa) if spa.eq_ignore_ascii_case("on") => AuthenticationType::Ntlm,
+            _ => AuthenticationType::PasswordCleartext,
+        };
+
+        properties.push(ServerProperty::Authentication(authentication));
+
+        if let Some(ews_url) = self.ews_url {
+            properties.push(ServerProperty::EwsURL(ews_url));
+        }
+
+        if let Some(owa_url) = self.owa_url {
+            properties.push(ServerProperty::OwaURL(owa_url));
+        }
+
+        Some(Server::new(server_type, properties))
+    }
+}
+
+fn response_error(error: ResponseError) -> Error {
+    Error::new(
+        ErrorKind::InvalidResponse,
+        format!(
+            "Autodiscover server returned an error ({}): {}",
+            error.error_code.unwrap_or_default(),
+            error.message.unwrap_or_default()
+        ),
+    )
+}
+
+/// Parses an Autodiscover response and maps the protocols it contains onto a [`Config`] for the given domain.
+pub fn from_bytes<B: AsRef<[u8]>, D: AsRef<str>, E: AsRef<str>>(
+    bytes: B,
+    domain: D,
+    email_address: E,
+) -> Result<Action> {
+    let reader = io::Cursor::new(bytes.as_ref());
+
+    let autodiscover: Autodiscover = serde_xml_rs::from_reader(reader)?;
+
+    let response = autodiscover.response;
+
+    if let Some(error) = response.error {
+        return Err(response_error(error));
+    }
+
+    let account = match response.account {
+        Some(account) => account,
+        None => {
+            return Err(Error::new(
+                ErrorKind::InvalidResponse,
+                "Autodiscover response did not contain any account information",
+            ))
+        }
+    };
+
+    if let Some(error) = account.error {
+        return Err(response_error(error));
+    }
+
+    match account.action.as_deref() {
+        Some("redirectUrl") => {
+            if let Some(url) = account.redirect_url {
+                return Ok(Action::RedirectUrl(url));
+            }
+        }
+        Some("redirectAddr") => {
+            if let Some(address) = account.redirect_addr {
+                return Ok(Action::RedirectAddr(address));
+            }
+        }
+        _ => {}
+    }
+
+    let mut properties = vec![EmailProviderProperty::Domain(domain.as_ref().to_string())];
+
+    for protocol in account.protocols {
+        let server_type = match protocol.server_type() {
+            Some(server_type) => server_type,
+            None => continue,
+        };
+
+        let is_outgoing = server_type == ServerType::Smtp;
+
+        if let Some(server) = protocol.into_server(server_type, email_address.as_ref()) {
+            if is_outgoing {
+                properties.push(EmailProviderProperty::OutgoingServer(server))
+            } else {
+                properties.push(EmailProviderProperty::IncomingServer(server))
+            }
+        }
+    }
+
+    if properties.len() < 2 {
+        return Err(Error::new(
+            ErrorKind::InvalidResponse,
+            "Autodiscover response did not contain any supported protocols",
+        ));
+    }
+
+    let provider = EmailProvider::new(domain.as_ref(), properties);
+
+    Ok(Action::Settings(Config::new(provider)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{SecurityType, ServerType};
+
+    use super::Action;
+
+    #[test]
+    fn test_parser() {
+        let mock_response = r#"
+            <?xml version="1.0" encoding="utf-8"?>
+            <Autodiscover xmlns="http://schemas.microsoft.com/exchange/autodiscover/responseschema/2006">
+                <Response xmlns="http://schemas.microsoft.com/exchange/autodiscover/outlook/responseschema/2006a">
+                    <User>
+                        <DisplayName>John Doe</DisplayName>
+                    </User>
+                    <Account>
+                        <AccountType>email</AccountType>
+                        <Action>settings</Action>
+                        <Protocol>
+                            <Type>EXCH</Type>
+                            <Server>outlook.example.com</Server>
+                            <EwsUrl>https://outlook.example.com/EWS/Exchange.asmx</EwsUrl>
+                        </Protocol>
+                        <Protocol>
+                            <Type>IMAP</Type>
+                            <Server>imap.example.com</Server>
+                            <Port>993</Port>
+                            <LoginName>john@example.com</LoginName>
+                            <SPA>off</SPA>
+                            <SSL>on</SSL>
+                        </Protocol>
+                        <Protocol>
+                            <Type>SMTP</Type>
+                            <Server>smtp.example.com</Server>
+                            <Port>587</Port>
+                            <LoginName>john</LoginName>
+                            <SPA>off</SPA>
+                            <Encryption>TLS</Encryption>
+                        </Protocol>
+                    </Account>
+                </Response>
+            </Autodiscover>
+        "#;
+
+        let action = super::from_bytes(mock_response, "example.com", "john@example.com").unwrap();
+
+        let config = match action {
+            Action::Settings(config) => config,
+            action => panic!("Expected settings, got {:?}", action),
+        };
+
+        let provider = config.email_provider();
+
+        assert_eq!(provider.domain(), vec!["example.com"]);
+
+        let incoming = provider.incoming_servers();
+
+        assert_eq!(incoming.len(), 2);
+        assert_eq!(incoming[0].server_type(), &ServerType::Exchange);
+        assert_eq!(incoming[1].hostname(), Some("imap.example.com"));
+        assert_eq!(incoming[1].security_type(), Some(&SecurityType::Tls));
+        assert_eq!(incoming[1].username(), Some("%EMAILADDRESS%"));
+
+        let outgoing = provider.outgoing_servers();
+
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].port(), Some(&587));
+        assert_eq!(outgoing[0].security_type(), Some(&SecurityType::Starttls));
+        assert_eq!(outgoing[0].username(), Some("%EMAILLOCALPART%"));
+    }
+
+    #[test]
+    fn test_redirect() {
+        let mock_response = r#"
+            <?xml version="1.0" encoding="utf-8"?>
+            <Autodiscover xmlns="http://schemas.microsoft.com/exchange/autodiscover/responseschema/2006">
+                <Response xmlns="http://schemas.microsoft.com/exchange/autodiscover/outlook/responseschema/2006a">
+                    <Account>
+                        <Action>redirectUrl</Action>
+                        <RedirectUrl>https://autodiscover.example.org/autodiscover/autodiscover.xml</RedirectUrl>
+                    </Account>
+                </Response>
+            </Autodiscover>
+        "#;
+
+        let action = super::from_bytes(mock_response, "example.com", "john@example.com").unwrap();
+
+        assert_eq!(
+            action,
+            Action::RedirectUrl(
+                "https://autodiscover.example.org/autodiscover/autodiscover.xml".to_string()
+            )
+        );
+    }
+}
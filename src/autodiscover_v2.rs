@@ -0,0 +1,339 @@
+//! Support for the JSON based Autodiscover v2 endpoint of Office 365, which returns the url of a single protocol without requiring authentication.
+//!
+//! This allows finding the servers of domains that are hosted by Office 365, but do not publish an Autodiscover service themselves.
+//! As the service answers for any domain, it is only asked about domains whose mail exchangers are run by Office 365, and only servers within Office 365 are accepted.
+
+use serde::Deserialize;
+use url::Url;
+
+use crate::{
+    config::{
+        AuthenticationType, Config, EmailProvider, EmailProviderProperty, SecurityType, Server,
+        ServerProperty, ServerType,
+    },
+    error::{Error, ErrorKind, Result},
+    utils,
+};
+
+const BASE_URL: &str = "https://autodiscover-s.outlook.com/autodiscover/autodiscover.json/v1.0/";
+
+const EMAIL_ADDRESS_PLACEHOLDER: &str = "%EMAILADDRESS%";
+
+/// The protocols that are requested, in the order their servers are added to the config.
+pub const PROTOCOLS: [&str; 5] = ["EWS", "ActiveSync", "IMAP", "POP3", "SMTP"];
+
+/// The domain under which Office 365 runs the mail exchangers of the domains it hosts, e.g. `contoso-com.mail.protection.outlook.com`.
+const MX_DOMAIN: &str = "protection.outlook.com";
+
+/// The domains under which Office 365 runs its servers.
+const SERVER_DOMAINS: [&str; 2] = ["office365.com", "outlook.com"];
+
+/// Whether any of a domain's mail exchangers is run by Office 365.
+pub fn is_hosted(exchanges: &[String]) -> bool {
+    exchanges
+        .iter()
+        .any(|exchange| utils::is_within_domain(exchange, MX_DOMAIN))
+}
+
+fn is_office365_server(hostname: &str) -> bool {
+    SERVER_DOMAINS
+        .iter()
+        .any(|domain| utils::is_within_domain(hostname, domain))
+}
+
+/// The url at which the endpoint of a protocol can be requested for an email address.
+pub fn url<E: AsRef<str>, P: AsRef<str>>(email_address: E, protocol: P) -> String {
+    let mut url = endpoint(email_address.as_ref());
+
+    url.query_pairs_mut()
+        .append_pair("Protocol", protocol.as_ref());
+
+    url.to_string()
+}
+
+/// The location at which the endpoints are requested for an email address, used to describe the source.
+pub fn location<E: AsRef<str>>(email_address: E) -> String {
+    endpoint(email_address.as_ref()).to_string()
+}
+
+fn endpoint(email_address: &str) -> Url {
+    let mut url = Url::parse(BASE_URL).unwrap();
+
+    // A quoted local part may contain characters like `/`, `?` and `#`, which must not change the meaning of the url.
+    url.path_segments_mut()
+        .unwrap()
+        .pop_if_empty()
+        .push(email_address);
+
+    url
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Response {
+    url: Option<String>,
+    error_code: Option<String>,
+    error_message: Option<String>,
+}
+
+/// Parses the response for a single protocol, returning the url of its endpoint.
+pub fn from_bytes<B: AsRef<[u8]>>(bytes: B) -> Result<String> {
+    let response: Response = serde_json::from_slice(bytes.as_ref())?;
+
+    match response.url {
+        Some(url) if !url.trim().is_empty() => Ok(url),
+        _ => Err(Error::new(
+            ErrorKind::InvalidResponse,
+            format!(
+                "Autodiscover server returned an error ({}): {}",
+                response.error_code.unwrap_or_default(),
+                response.error_message.unwrap_or_default()
+            ),
+        )),
+    }
+}
+
+/// Splits an endpoint like `imaps://outlook.office365.com:993` or `outlook.office365.com` into its scheme, hostname and port.
+fn split_endpoint(endpoint: &str) -> (Option<&str>, &str, Option<u16>) {
+    let (scheme, rest) = match endpoint.split_once("://") {
+        Some((scheme, rest)) => (Some(scheme), rest),
+        None => (None, endpoint),
+    };
+
+    let authority = rest.split('/').next().unwrap_or_default();
+
+    match authority.rsplit_once(':') {
+        Some((hostname, port)) => match port.parse() {
+            Ok(port) => (scheme, hostname, Some(port)),
+            Err(_) => (scheme, authority, None),
+        },
+        None => (scheme, authority, None),
+    }
+}
+
+/// Maps the endpoint of a protocol onto a server, mail protocols without an explicit scheme or port use their implicit TLS (or submission) defaults.
+fn into_server(protocol: &str, endpoint: &str) -> Option<Server> {
+    let (scheme, hostname, port) = split_endpoint(endpoint);
+
+    if hostname.is_empty() {
+        return None;
+    }
+
+    let server =
+        match protocol.to_ascii_uppercase().as_str() {
+            "EWS" => Server::exchange(hostname)
+                .with_property(ServerProperty::EwsURL(endpoint.to_string())),
+            "ACTIVESYNC" => Server::exchange(hostname)
+                .with_property(ServerProperty::EasURL(endpoint.to_string())),
+            "IMAP" => match scheme {
+                Some("imap") => Server::imap(hostname, port.unwrap_or(143), SecurityType::Starttls),
+                _ => Server::imap(hostname, port.unwrap_or(993), SecurityType::Tls),
+            },
+            "POP3" => match scheme {
+                Some("pop3") | Some("pop") => {
+                    Server::pop3(hostname, port.unwrap_or(110), SecurityType::Starttls)
+                }
+                _ => Server::pop3(hostname, port.unwrap_or(995), SecurityType::Tls),
+            },
+            "SMTP" => match scheme {
+                Some("smtps") => Server::smtp(hostname, port.unwrap_or(465), SecurityType::Tls),
+                _ => Server::smtp(hostname, port.unwrap_or(587), SecurityType::Starttls),
+            },
+            _ => return None,
+        };
+
+    // Office 365 no longer accepts passwords for these protocols.
+    Some(
+        server
+            .with_username(EMAIL_ADDRESS_PLACEHOLDER)
+            .with_authentication(AuthenticationType::OAuth2),
+    )
+}
+
+/// Creates a config from the endpoints that were returned for each protocol.
+///
+/// The answer is rejected if any of the endpoints is not run by Office 365.
+pub fn into_config<D: AsRef<str>>(domain: D, endpoints: Vec<(&str, String)>) -> Result<Config> {
+    let mut properties = vec![EmailProviderProperty::Domain(domain.as_ref().to_string())];
+
+    let mut exchange: Option<Server> = None;
+
+    for (protocol, endpoint) in endpoints {
+        let server = match into_server(protocol, &endpoint) {
+            Some(server) => server,
+            None => continue,
+        };
+
+        if !server.hostname().map_or(false, is_office365_server) {
+            return Err(Error::new(
+                ErrorKind::Insecure,
+                format!(
+                    "Autodiscover v2 returned a server outside of Office 365: {}",
+                    endpoint
+                ),
+            ));
+        }
+
+        match server.server_type() {
+            // EWS and ActiveSync are usually served by the same host, so their urls are merged into one server.
+            ServerType::Exchange => match exchange.take() {
+                Some(existing) if existing.hostname() == server.hostname() => {
+                    let url = server.properties().iter().find(|property| {
+                        matches!(
+                            property,
+                            ServerProperty::EwsURL(_) | ServerProperty::EasURL(_)
+                        )
+                    });
+
+                    exchange = Some(match url {
+                        Some(url) => existing.with_property(url.clone()),
+                        None => existing,
+                    });
+                }
+                Some(existing) => {
+                    properties.push(EmailProviderProperty::IncomingServer(existing));
+
+                    exchange = Some(server);
+                }
+                None => exchange = Some(server),
+            },
+            ServerType::Smtp => properties.push(EmailProviderProperty::OutgoingServer(server)),
+            _ => properties.push(EmailProviderProperty::IncomingServer(server)),
+        }
+    }
+
+    if let Some(exchange) = exchange {
+        properties.insert(1, EmailProviderProperty::IncomingServer(exchange));
+    }
+
+    if properties.len() < 2 {
+        return Err(Error::new(
+            ErrorKind::InvalidResponse,
+            "Autodiscover did not return an endpoint for any supported protocol",
+        ));
+    }
+
+    let provider = EmailProvider::new(domain.as_ref(), properties);
+
+    Ok(Config::new(provider))
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use crate::{
+        address::EmailAddress,
+        config::{SecurityType, ServerProperty, ServerType},
+    };
+
+    #[test]
+    fn test_url() {
+        assert_eq!(
+            super::url("user@example.com", "EWS"),
+            "https://autodiscover-s.outlook.com/autodiscover/autodiscover.json/v1.0/user@example.com?Protocol=EWS"
+        );
+        assert_eq!(
+            super::location("a/b c@example.com"),
+            "https://autodiscover-s.outlook.com/autodiscover/autodiscover.json/v1.0/a%2Fb%20c@example.com"
+        );
+    }
+
+    #[test]
+    fn test_url_quoted_local_part() {
+        for (address, path) in [
+            (
+                r#""a?Protocol=x#"@contoso.com"#,
+                "/v1.0/%22a%3FProtocol=x%23%22@contoso.com",
+            ),
+            (r#""../../x"@d.com"#, "/v1.0/%22..%2F..%2Fx%22@d.com"),
+        ] {
+            let address = EmailAddress::parse(address).unwrap();
+
+            let url = Url::parse(&super::url(address.to_string(), "EWS")).unwrap();
+
+            assert!(url.path().ends_with(path), "{}", url);
+            assert_eq!(url.query(), Some("Protocol=EWS"));
+            assert_eq!(url.fragment(), None);
+        }
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let ews = r#"{"Protocol":"EWS","Url":"https://outlook.office365.com/EWS/Exchange.asmx"}"#;
+
+        assert_eq!(
+            super::from_bytes(ews).unwrap(),
+            "https://outlook.office365.com/EWS/Exchange.asmx"
+        );
+
+        let error = r#"{"ErrorCode":"InvalidProtocol","ErrorMessage":"The given protocol value 'Foo' is invalid."}"#;
+
+        assert!(super::from_bytes(error).is_err());
+    }
+
+    #[test]
+    fn test_into_config() {
+        let endpoints = vec![
+            (
+                "EWS",
+                "https://outlook.office365.com/EWS/Exchange.asmx".to_string(),
+            ),
+            (
+                "ActiveSync",
+                "https://outlook.office365.com/Microsoft-Server-ActiveSync".to_string(),
+            ),
+            ("IMAP", "outlook.office365.com".to_string()),
+            ("SMTP", "smtp://smtp.office365.com:587".to_string()),
+        ];
+
+        let config = super::into_config("example.com", endpoints).unwrap();
+
+        let provider = config.email_provider();
+
+        let incoming = provider.incoming_servers();
+
+        assert_eq!(incoming.len(), 2);
+        assert_eq!(incoming[0].server_type(), &ServerType::Exchange);
+        assert!(incoming[0].properties().contains(&ServerProperty::EwsURL(
+            "https://outlook.office365.com/EWS/Exchange.asmx".to_string()
+        )));
+        assert!(incoming[0]
+            .properties()
+            .iter()
+            .any(|property| matches!(property, ServerProperty::EasURL(_))));
+        assert_eq!(incoming[1].hostname(), Some("outlook.office365.com"));
+        assert_eq!(incoming[1].port(), Some(&993));
+        assert_eq!(incoming[1].security_type(), Some(&SecurityType::Tls));
+
+        let outgoing = provider.outgoing_servers();
+
+        assert_eq!(outgoing[0].hostname(), Some("smtp.office365.com"));
+        assert_eq!(outgoing[0].security_type(), Some(&SecurityType::Starttls));
+
+        let endpoints = vec![("POP3", "outlook.office365.com".to_string())];
+
+        let config = super::into_config("example.com", endpoints).unwrap();
+
+        let incoming = config.email_provider().incoming_servers();
+
+        assert_eq!(incoming[0].server_type(), &ServerType::Pop3);
+        assert_eq!(incoming[0].port(), Some(&995));
+    }
+
+    #[test]
+    fn test_office365_only() {
+        assert!(super::is_hosted(&[
+            "contoso-com.mail.protection.outlook.com".to_string()
+        ]));
+        assert!(!super::is_hosted(&["mx.example.com".to_string()]));
+        assert!(!super::is_hosted(&[]));
+
+        let endpoints = vec![
+            ("IMAP", "outlook.office365.com".to_string()),
+            ("SMTP", "smtp.example.com".to_string()),
+        ];
+
+        assert!(super::into_config("example.com", endpoints).is_err());
+    }
+}
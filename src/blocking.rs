@@ -0,0 +1,184 @@
+//! A synchronous version of the API, for programs that do not run an async runtime themselves.
+//!
+//! Every [`Client`] manages its own single threaded runtime, which only runs while a lookup is in progress.
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    client,
+    config::Config,
+    discovery::Candidate,
+    dns::Dns,
+    error::{Error, ErrorKind, Result},
+    transport::{DnsResolver, HttpTransport},
+};
+
+#[cfg(feature = "http-curl")]
+use crate::{client::ClientBuilder, discovery::Strategy, http::Http};
+
+fn runtime() -> Result<Runtime> {
+    Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|error| Error::new(ErrorKind::Io(error), "Failed to start the runtime"))
+}
+
+/// A blocking version of [`crate::Client`], see its documentation for the details of each lookup.
+pub struct Client<
+    #[cfg(feature = "http-curl")] H = Http,
+    #[cfg(not(feature = "http-curl"))] H,
+    R = Dns,
+> {
+    client: client::Client<H, R>,
+    runtime: Runtime,
+}
+
+#[cfg(feature = "http-curl")]
+impl Client {
+    /// Creates a client that uses the default http client and DNS resolver, with the default options.
+    pub fn new() -> Result<Self> {
+        Self::with_builder(ClientBuilder::default())
+    }
+
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    /// Creates a client that uses the default http client and DNS resolver, with the options of the given builder.
+    pub fn with_builder(builder: ClientBuilder) -> Result<Self> {
+        let runtime = runtime()?;
+
+        let client = runtime.block_on(builder.build())?;
+
+        Ok(Self { client, runtime })
+    }
+}
+
+impl<H: HttpTransport, R: DnsResolver> Client<H, R> {
+    /// Creates a client that uses the given http client and DNS resolver, with the default options.
+    pub fn with_transports(http: H, dns: R) -> Result<Self> {
+        Ok(Self {
+            client: client::Client::with_transports(http, dns),
+            runtime: runtime()?,
+        })
+    }
+
+    /// See [`crate::Client::lookup_domain`].
+    pub fn lookup_domain<D: AsRef<str>>(&self, domain: D) -> Result<Config> {
+        self.runtime.block_on(self.client.lookup_domain(domain))
+    }
+
+    /// See [`crate::Client::lookup_addr`].
+    pub fn lookup_addr<E: AsRef<str>>(&self, email_address: E) -> Result<Config> {
+        self.runtime
+            .block_on(self.client.lookup_addr(email_address))
+    }
+
+    /// See [`crate::Client::discover_all`].
+    pub fn discover_all<D: AsRef<str>>(&self, domain: D) -> Vec<Candidate> {
+        self.runtime.block_on(self.client.discover_all(domain))
+    }
+}
+
+/// The blocking version of [`crate::from_domain`].
+#[cfg(feature = "http-curl")]
+pub fn from_domain<D: AsRef<str>>(domain: D) -> Result<Config> {
+    from_domain_with_strategy(domain, Strategy::default())
+}
+
+/// The blocking version of [`crate::from_domain_with_strategy`].
+#[cfg(feature = "http-curl")]
+pub fn from_domain_with_strategy<D: AsRef<str>>(domain: D, strategy: Strategy) -> Result<Config> {
+    let client = Client::with_builder(ClientBuilder::default().strategy(strategy))?;
+
+    client.lookup_domain(domain)
+}
+
+/// The blocking version of [`crate::discover_all`].
+#[cfg(feature = "http-curl")]
+pub fn discover_all<D: AsRef<str>>(domain: D) -> Result<Vec<Candidate>> {
+    let client = Client::new()?;
+
+    Ok(client.discover_all(domain))
+}
+
+/// The blocking version of [`crate::from_addr`].
+#[cfg(feature = "http-curl")]
+pub fn from_addr(email_address: &str) -> Result<Config> {
+    from_addr_with_strategy(email_address, Strategy::default())
+}
+
+/// The blocking version of [`crate::from_addr_with_strategy`].
+#[cfg(feature = "http-curl")]
+pub fn from_addr_with_strategy(email_address: &str, strategy: Strategy) -> Result<Config> {
+    let client = Client::with_builder(ClientBuilder::default().strategy(strategy))?;
+
+    client.lookup_addr(email_address)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use bytes::Bytes;
+
+    use crate::{
+        error::{Error, ErrorKind, Result},
+        transport::{
+            DnsResolver, HttpTransport, MxRecord, Request, Response, SrvRecord, TxtRecord,
+        },
+    };
+
+    use super::Client;
+
+    const ISPDB_CONFIG: &str = r#"
+        <clientConfig version="1.1">
+            <emailProvider id="ispdb">
+                <domain>example.com</domain>
+            </emailProvider>
+        </clientConfig>
+    "#;
+
+    struct MockHttp;
+
+    #[async_trait]
+    impl HttpTransport for MockHttp {
+        async fn send(&self, request: Request) -> Result<Response> {
+            match request.url() {
+                "https://autoconfig.thunderbird.net/v1.1/example.com" => {
+                    Ok(Response::new(200, ISPDB_CONFIG))
+                }
+                _ => Ok(Response::new(404, "Not found")),
+            }
+        }
+    }
+
+    struct MockDns;
+
+    fn no_records() -> Error {
+        Error::new(ErrorKind::NoRecordsFound, "No records found")
+    }
+
+    #[async_trait]
+    impl DnsResolver for MockDns {
+        async fn txt(&self, _name: &str) -> Result<Vec<TxtRecord>> {
+            Err(no_records())
+        }
+
+        async fn srv(&self, _name: &str) -> Result<Vec<SrvRecord>> {
+            Err(no_records())
+        }
+
+        async fn mx(&self, _name: &str) -> Result<Vec<MxRecord>> {
+            Err(no_records())
+        }
+    }
+
+    #[test]
+    fn test_blocking_client() {
+        let client = Client::with_transports(MockHttp, MockDns).unwrap();
+
+        let config = client.lookup_addr("user@example.com").unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+    }
+}
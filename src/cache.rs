@@ -0,0 +1,651 @@
+//! Caching of discovery results, so popular domains do not have to be looked up over and over again.
+//!
+//! The outcome of every source is cached separately, for as long as the source allows: configs fetched over http use the `Cache-Control` and `Expires` headers, configs based on DNS records use the TTL of those records.
+//! Sources that permanently failed to return a config are cached as well, see [`Cache::negative_ttl`].
+//!
+//! The DNS records that are queried before the sources, like the domain's mail exchangers, are cached for as long as their TTL allows.
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use blocking::unblock;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    discovery::Source,
+    error::{Error, ErrorKind, Result},
+    transport::Response,
+};
+
+/// Identifies a cached outcome: the domain that was looked up and the source that was queried for it.
+///
+/// Sources that return settings for a specific account, like Autodiscover, also include the email address that was looked up.
+/// DNS records that a source depends on are cached under the type of record that was queried.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    domain: String,
+    email_address: Option<String>,
+    record_type: Option<String>,
+    source: Source,
+}
+
+impl Key {
+    pub(crate) fn new<D: Into<String>>(domain: D, source: Source) -> Self {
+        Self {
+            domain: domain.into(),
+            email_address: None,
+            record_type: None,
+            source,
+        }
+    }
+
+    pub(crate) fn with_email_address<E: Into<String>>(mut self, email_address: E) -> Self {
+        self.email_address = Some(email_address.into());
+
+        self
+    }
+
+    pub(crate) fn with_record_type<T: Into<String>>(mut self, record_type: T) -> Self {
+        self.record_type = Some(record_type.into());
+
+        self
+    }
+
+    /// The domain that was looked up.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// The email address that was looked up, if the outcome depends on it.
+    pub fn email_address(&self) -> Option<&str> {
+        self.email_address.as_deref()
+    }
+
+    /// The type of DNS record that was queried, like `MX`, if the key is for records instead of a config.
+    pub fn record_type(&self) -> Option<&str> {
+        self.record_type.as_deref()
+    }
+
+    /// The source that was queried, which includes its mechanism.
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.email_address, &self.record_type) {
+            (Some(email_address), _) => write!(
+                f,
+                "{} ({}) from {}",
+                self.domain, email_address, self.source
+            ),
+            (None, Some(record_type)) => write!(
+                f,
+                "{} ({} records) from {}",
+                self.domain, record_type, self.source
+            ),
+            (None, None) => write!(f, "{} from {}", self.domain, self.source),
+        }
+    }
+}
+
+/// What is cached for a key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Value {
+    /// The config a source returned.
+    Config(Config),
+    /// The records a DNS query returned, e.g. the hostnames of a domain's mail exchangers.
+    Records(Vec<String>),
+}
+
+/// A cached outcome of a source or DNS query.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    value: Option<Value>,
+    expires: SystemTime,
+}
+
+impl Entry {
+    pub fn new(value: Option<Value>, expires: SystemTime) -> Self {
+        Self { value, expires }
+    }
+
+    /// What was cached, or `None` if the source did not have a config or the name did not have any records.
+    pub fn value(&self) -> Option<&Value> {
+        self.value.as_ref()
+    }
+
+    /// The config the source returned, if the entry is for a config.
+    pub fn config(&self) -> Option<&Config> {
+        match &self.value {
+            Some(Value::Config(config)) => Some(config),
+            _ => None,
+        }
+    }
+
+    /// The records the DNS query returned, if the entry is for records.
+    pub fn records(&self) -> Option<&[String]> {
+        match &self.value {
+            Some(Value::Records(records)) => Some(records),
+            _ => None,
+        }
+    }
+
+    /// When the entry should no longer be used.
+    pub fn expires(&self) -> SystemTime {
+        self.expires
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires <= SystemTime::now()
+    }
+}
+
+/// Somewhere cache entries can be stored, e.g. in memory, on disk or in a shared database.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// The entry for a key, expired entries may be returned as they are ignored anyway.
+    async fn get(&self, key: &Key) -> Result<Option<Entry>>;
+
+    /// Stores an entry, replacing any existing entry for the same key.
+    async fn set(&self, key: &Key, entry: Entry) -> Result<()>;
+
+    async fn remove(&self, key: &Key) -> Result<()>;
+}
+
+/// Stores entries in memory, so they are lost when the program exits.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get(&self, key: &Key) -> Result<Option<Entry>> {
+        let entries = self.entries.lock().unwrap();
+
+        Ok(entries.get(key).cloned())
+    }
+
+    async fn set(&self, key: &Key, entry: Entry) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+
+        // Every key that is looked up once tends to be looked up again, so expired entries are only cleaned up when new ones come in.
+        entries.retain(|_, entry| !entry.is_expired());
+        entries.insert(key.clone(), entry);
+
+        Ok(())
+    }
+
+    async fn remove(&self, key: &Key) -> Result<()> {
+        self.entries.lock().unwrap().remove(key);
+
+        Ok(())
+    }
+}
+
+/// Stores every entry as a JSON file in a directory, so they can be shared between runs and processes.
+///
+/// The files are read and written on a shared pool of threads, so a slow disk does not block the executor.
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    directory: PathBuf,
+}
+
+/// The contents of a cache file.
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    key: String,
+    expires: u64,
+    value: Option<Value>,
+}
+
+/// The 64 bit FNV-1a hash, which unlike the std hasher is guaranteed to be the same between builds.
+fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn io_error(error: io::Error) -> Error {
+    Error::new(ErrorKind::Io(error), "Failed to access the cache")
+}
+
+impl FileStorage {
+    /// Stores the entries in the given directory, which is created if it does not exist.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, key: &Key) -> PathBuf {
+        self.directory
+            .join(format!("{:016x}.json", fnv1a(&key.to_string())))
+    }
+}
+
+#[async_trait]
+impl Storage for FileStorage {
+    async fn get(&self, key: &Key) -> Result<Option<Entry>> {
+        let path = self.path(key);
+        let key = key.to_string();
+
+        unblock(move || {
+            let bytes = match fs::read(path) {
+                Ok(bytes) => bytes,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(error) => return Err(io_error(error)),
+            };
+
+            let stored: StoredEntry = serde_json::from_slice(&bytes)?;
+
+            // Two keys could end up with the same file name.
+            if stored.key != key {
+                return Ok(None);
+            }
+
+            Ok(Some(Entry::new(
+                stored.value,
+                UNIX_EPOCH + Duration::from_secs(stored.expires),
+            )))
+        })
+        .await
+    }
+
+    async fn set(&self, key: &Key, entry: Entry) -> Result<()> {
+        let stored = StoredEntry {
+            key: key.to_string(),
+            expires: entry
+                .expires
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            value: entry.value,
+        };
+
+        let bytes = serde_json::to_vec(&stored)?;
+
+        let directory = self.directory.clone();
+        let path = self.path(key);
+
+        unblock(move || {
+            fs::create_dir_all(directory).map_err(io_error)?;
+
+            let temporary = path.with_extension("tmp");
+
+            // Writing to a temporary file first makes sure a reader never sees a partially written entry.
+            fs::write(&temporary, bytes).map_err(io_error)?;
+            fs::rename(&temporary, &path).map_err(io_error)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove(&self, key: &Key) -> Result<()> {
+        let path = self.path(key);
+
+        unblock(move || match fs::remove_file(path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(io_error(error)),
+            _ => Ok(()),
+        })
+        .await
+    }
+}
+
+/// Caches the outcome of discovery sources in a [`Storage`], see [`crate::ClientBuilder::cache`].
+///
+/// Failing to read from or write to the storage never fails a lookup, the source is simply queried instead.
+#[derive(Clone)]
+pub struct Cache {
+    storage: Arc<dyn Storage>,
+    default_ttl: Duration,
+    negative_ttl: Duration,
+    max_ttl: Duration,
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("default_ttl", &self.default_ttl)
+            .field("negative_ttl", &self.negative_ttl)
+            .field("max_ttl", &self.max_ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Cache {
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+    pub const NEGATIVE_TTL: Duration = Duration::from_secs(5 * 60);
+    pub const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+    pub fn new<S: Storage + 'static>(storage: S) -> Self {
+        Self {
+            storage: Arc::new(storage),
+            default_ttl: Self::DEFAULT_TTL,
+            negative_ttl: Self::NEGATIVE_TTL,
+            max_ttl: Self::MAX_TTL,
+        }
+    }
+
+    /// A cache that is kept in memory, see [`MemoryStorage`].
+    pub fn memory() -> Self {
+        Self::new(MemoryStorage::new())
+    }
+
+    /// A cache that is kept in the given directory, see [`FileStorage`].
+    pub fn file<P: Into<PathBuf>>(directory: P) -> Self {
+        Self::new(FileStorage::new(directory))
+    }
+
+    /// How long to cache a config if its source does not say, defaults to an hour.
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = ttl;
+
+        self
+    }
+
+    /// How long to remember that a source permanently failed to return a config, e.g. because it does not exist. Defaults to five minutes.
+    ///
+    /// Transient failures, see [`Error::is_transient`], are never cached.
+    pub fn negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl;
+
+        self
+    }
+
+    /// The longest a config is cached, regardless of what its source says. Defaults to a day.
+    pub fn max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = ttl;
+
+        self
+    }
+
+    /// The cached outcome of a source, if there is one that has not expired yet.
+    pub(crate) async fn get(&self, key: &Key) -> Option<Result<Config>> {
+        match self.entry(key).await?.value {
+            Some(Value::Config(config)) => Some(Ok(config)),
+            Some(Value::Records(_)) => None,
+            None => Some(Err(Error::new(
+                ErrorKind::NotFound(Vec::new()),
+                "Source did not have a config the last time it was queried",
+            ))),
+        }
+    }
+
+    /// The cached answer to a DNS query along with how much longer it may be used, if there is one that has not expired yet.
+    pub(crate) async fn get_records(
+        &self,
+        key: &Key,
+    ) -> Option<Result<(Vec<String>, Option<Duration>)>> {
+        let entry = self.entry(key).await?;
+
+        let ttl = entry.expires.duration_since(SystemTime::now()).ok();
+
+        match entry.value {
+            Some(Value::Records(records)) => Some(Ok((records, ttl))),
+            Some(Value::Config(_)) => None,
+            None => Some(Err(Error::new(
+                ErrorKind::NoRecordsFound,
+                "Name did not have any records the last time it was queried",
+            ))),
+        }
+    }
+
+    async fn entry(&self, key: &Key) -> Option<Entry> {
+        let entry = self.storage.get(key).await.ok()??;
+
+        if entry.is_expired() {
+            let _ = self.storage.remove(key).await;
+
+            return None;
+        }
+
+        Some(entry)
+    }
+
+    /// Caches the outcome of a source, as far as it may be cached.
+    pub(crate) async fn set(&self, key: &Key, result: &Result<Config>) {
+        match result {
+            Ok(config) => {
+                self.store(key, Some(Value::Config(config.clone())), config.max_age())
+                    .await
+            }
+            Err(error) if error.is_permanent() => {
+                self.store(key, None, Some(self.negative_ttl)).await
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Caches the answer to a DNS query for as long as the TTL of its records allows.
+    pub(crate) async fn set_records(
+        &self,
+        key: &Key,
+        result: &Result<(Vec<String>, Option<Duration>)>,
+    ) {
+        match result {
+            Ok((records, ttl)) => {
+                self.store(key, Some(Value::Records(records.clone())), *ttl)
+                    .await
+            }
+            Err(error) if error.is_permanent() => {
+                self.store(key, None, Some(self.negative_ttl)).await
+            }
+            Err(_) => {}
+        }
+    }
+
+    async fn store(&self, key: &Key, value: Option<Value>, ttl: Option<Duration>) {
+        let ttl = ttl.unwrap_or(self.default_ttl).min(self.max_ttl);
+
+        if ttl.is_zero() {
+            let _ = self.storage.remove(key).await;
+
+            return;
+        }
+
+        let _ = self
+            .storage
+            .set(key, Entry::new(value, SystemTime::now() + ttl))
+            .await;
+    }
+}
+
+/// How long a response may be cached according to its `Cache-Control` or `Expires` header.
+pub(crate) fn max_age(response: &Response) -> Option<Duration> {
+    if let Some(cache_control) = response.header("Cache-Control") {
+        let mut max_age = None;
+
+        for directive in cache_control.split(',') {
+            let directive = directive.trim().to_ascii_lowercase();
+
+            match directive.split_once('=') {
+                Some(("max-age", seconds)) => {
+                    max_age = seconds
+                        .trim_matches('"')
+                        .parse()
+                        .ok()
+                        .map(Duration::from_secs)
+                }
+                None if directive == "no-store" || directive == "no-cache" => {
+                    return Some(Duration::ZERO)
+                }
+                _ => {}
+            }
+        }
+
+        if max_age.is_some() {
+            return max_age;
+        }
+    }
+
+    let expires = parse_http_date(response.header("Expires")?)?;
+
+    // An invalid or past date means the response is already expired.
+    Some(
+        expires
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Parses a date in the format http uses, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let mut parts = date.split_whitespace().skip(1);
+
+    let day: u64 = parts.next()?.parse().ok()?;
+
+    let month = parts.next()?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|name| name == &month)? as u64 + 1;
+
+    let year: u64 = parts.next()?.parse().ok()?;
+
+    let mut time = parts.next()?.split(':').map(|part| part.parse::<u64>());
+
+    let (hours, minutes, seconds) = match (time.next()?, time.next()?, time.next()?) {
+        (Ok(hours), Ok(minutes), Ok(seconds)) => (hours, minutes, seconds),
+        _ => return None,
+    };
+
+    if year < 1970 || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    // The number of days since the unix epoch, using the days from civil algorithm.
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+
+    let era = y / 400;
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let seconds = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use futures::executor::block_on;
+
+    use crate::{
+        config::{Config, SecurityType, Server},
+        discovery::{Mechanism, Source, Trust},
+        error::{Error, ErrorKind, Result},
+        transport::Response,
+    };
+
+    use super::{Cache, Key};
+
+    #[test]
+    fn test_max_age() {
+        let response = |name: &str, value: &str| Response::new(200, "").with_header(name, value);
+
+        assert_eq!(
+            super::max_age(&response("Cache-Control", "public, max-age=600")),
+            Some(Duration::from_secs(600))
+        );
+        assert_eq!(
+            super::max_age(&response("Cache-Control", "no-store")),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            super::max_age(&response("Expires", "Sun, 06 Nov 1994 08:49:37 GMT")),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(super::max_age(&Response::new(200, "")), None);
+
+        assert_eq!(
+            super::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(784111777))
+        );
+    }
+
+    fn roundtrip(cache: Cache) {
+        let key = Key::new(
+            "example.com",
+            Source::new(Mechanism::Ispdb, "https://example.com"),
+        );
+
+        let mut config = Config::builder()
+            .provider("example.com")
+            .domain("example.com")
+            .incoming(Server::imap("imap.example.com", 993, SecurityType::Tls))
+            .build()
+            .unwrap();
+
+        config.set_trust(Trust::Secure);
+
+        block_on(cache.set(&key, &Ok(config.clone())));
+
+        let cached = block_on(cache.get(&key)).unwrap().unwrap();
+
+        assert_eq!(cached.email_provider().id(), "example.com");
+        assert_eq!(cached.trust(), Some(&Trust::Secure));
+
+        let not_found: Result<Config> = Err(Error::new(ErrorKind::NotFound(Vec::new()), ""));
+
+        block_on(cache.set(&key, &not_found));
+
+        assert!(block_on(cache.get(&key)).unwrap().is_err());
+
+        let timeout: Result<Config> = Err(Error::new(ErrorKind::Timeout, ""));
+
+        // A transient error does not replace the entry.
+        block_on(cache.set(&key, &timeout));
+
+        assert!(block_on(cache.get(&key)).is_some());
+
+        let mut uncacheable = config;
+
+        uncacheable.limit_max_age(Some(Duration::ZERO));
+
+        // A config that may not be cached removes the outdated entry, as the source does have a config now.
+        block_on(cache.set(&key, &Ok(uncacheable)));
+
+        assert!(block_on(cache.get(&key)).is_none());
+    }
+
+    #[test]
+    fn test_memory_cache() {
+        roundtrip(Cache::memory());
+    }
+
+    #[test]
+    fn test_file_cache() {
+        let directory =
+            std::env::temp_dir().join(format!("autoconfig-cache-{}", std::process::id()));
+
+        roundtrip(Cache::file(&directory));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
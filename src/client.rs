@@ -1,55 +1,2427 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::{
+    future::{join_all, select, select_ok, BoxFuture, Either},
+    stream::FuturesUnordered,
+    FutureExt, StreamExt,
+};
+use futures_timer::Delay;
 use regex::Regex;
-use surf::Url;
+use url::Url;
+
+use crate::{
+    address::{self, EmailAddress},
+    autodiscover::{self, Action},
+    autodiscover_v2,
+    cache::{self, Cache, Key},
+    config::Config,
+    discovery::{Candidate, Mechanism, RetryPolicy, SecurityPolicy, Source, Strategy, Trust},
+    dns::Dns,
+    error::{Error, ErrorKind, Result},
+    ispdb::{Snapshot, SnapshotMode},
+    parse,
+    registry::ProviderRegistry,
+    srv,
+    transport::{DnsResolver, HttpTransport, Request, Response},
+    utils, ResolverConfig, ResolverOpts, Runtime,
+};
+
+#[cfg(feature = "http-curl")]
+use crate::http::Http;
+#[cfg(feature = "verify")]
+use crate::{guess, verify::Verifier};
+
+const AT_SYMBOL: char = '@';
+const POSTMASTER: &str = "postmaster";
+
+/// Queries the different discovery sources for a domain.
+///
+/// A client can be reused for many lookups. By default it uses `Http` and [`Dns`] to query the sources, but any [`HttpTransport`] and [`DnsResolver`] can be used instead, see [`ClientBuilder::build_with`].
+pub struct Client<
+    #[cfg(feature = "http-curl")] H = Http,
+    #[cfg(not(feature = "http-curl"))] H,
+    R = Dns,
+> {
+    http: H,
+    dns: R,
+    ispdb_url: String,
+    strategy: Strategy,
+    disabled: Vec<Mechanism>,
+    send_email_address: bool,
+    security_policy: SecurityPolicy,
+    retry_policy: RetryPolicy,
+    cache: Option<Cache>,
+    registry: Option<Arc<ProviderRegistry>>,
+    snapshot: Option<Arc<Snapshot>>,
+    snapshot_mode: SnapshotMode,
+    #[cfg(feature = "verify")]
+    verifier: Verifier,
+}
+
+/// Used to configure a [`Client`].
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    timeout: Duration,
+    user_agent: Option<String>,
+    resolver_config: ResolverConfig,
+    resolver_opts: ResolverOpts,
+    runtime: Option<Runtime>,
+    ispdb_url: String,
+    strategy: Strategy,
+    disabled: Vec<Mechanism>,
+    send_email_address: bool,
+    security_policy: SecurityPolicy,
+    retry_policy: RetryPolicy,
+    cache: Option<Cache>,
+    registry: Option<Arc<ProviderRegistry>>,
+    snapshot: Option<Arc<Snapshot>>,
+    snapshot_mode: SnapshotMode,
+    #[cfg(feature = "verify")]
+    verifier: Verifier,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            timeout: ClientBuilder::TIMEOUT,
+            user_agent: None,
+            resolver_config: ResolverConfig::default(),
+            resolver_opts: ResolverOpts::default(),
+            runtime: None,
+            ispdb_url: String::from(ClientBuilder::ISPDB_URL),
+            strategy: Strategy::default(),
+            disabled: Vec::new(),
+            send_email_address: false,
+            security_policy: SecurityPolicy::default(),
+            retry_policy: RetryPolicy::default(),
+            cache: None,
+            registry: None,
+            #[cfg(feature = "bundled-ispdb")]
+            snapshot: Some(Arc::new(Snapshot::bundled())),
+            #[cfg(not(feature = "bundled-ispdb"))]
+            snapshot: None,
+            snapshot_mode: SnapshotMode::default(),
+            #[cfg(feature = "verify")]
+            verifier: Verifier::new(),
+        }
+    }
+}
+
+impl ClientBuilder {
+    const ISPDB_URL: &str = "https://autoconfig.thunderbird.net/v1.1/";
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long a single http request may take before it is aborted, only applies to the default http client.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+
+        self
+    }
+
+    /// The user agent to send with every http request, only applies to the default http client.
+    pub fn user_agent<U: Into<String>>(mut self, user_agent: U) -> Self {
+        self.user_agent = Some(user_agent.into());
+
+        self
+    }
+
+    /// The configuration for the default DNS resolver, by default the system's configuration is used.
+    pub fn resolver_config(mut self, config: ResolverConfig, opts: ResolverOpts) -> Self {
+        self.resolver_config = config;
+        self.resolver_opts = opts;
+
+        self
+    }
+
+    /// The runtime the default DNS resolver runs on, by default it is picked when the client is built, see [`Runtime::default`].
+    pub fn runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = Some(runtime);
+
+        self
+    }
+
+    /// The base url of the ISPDB, the domain that is looked up is appended to it. Defaults to Mozilla's ISPDB.
+    pub fn ispdb_url<U: Into<String>>(mut self, url: U) -> Self {
+        let mut url = url.into();
+
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+
+        self.ispdb_url = url;
+
+        self
+    }
+
+    /// Which config to return when multiple sources return one.
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+
+        self
+    }
+
+    /// Enables a discovery mechanism, all mechanisms are enabled by default.
+    pub fn enable(mut self, mechanism: Mechanism) -> Self {
+        self.disabled.retain(|disabled| disabled != &mechanism);
+
+        self
+    }
+
+    /// Disables a discovery mechanism, so it will never be queried.
+    pub fn disable(mut self, mechanism: Mechanism) -> Self {
+        if !self.disabled.contains(&mechanism) {
+            self.disabled.push(mechanism);
+        }
+
+        self
+    }
+
+    /// Whether to send the email address that is looked up as the `emailaddress` query parameter to autoconfig urls hosted by the provider, so they can return a config specific to the user.
+    ///
+    /// The address is also sent to the Autodiscover services, which are asked for the `postmaster` of the domain instead when this is disabled, as it is by default.
+    /// The address is never sent to the ISPDB or over plain http, and lookups by domain never send one.
+    pub fn send_email_address(mut self, send: bool) -> Self {
+        self.send_email_address = send;
+
+        self
+    }
+
+    /// Whether configs hosted by the provider may be fetched over plain http, defaults to [`SecurityPolicy::HttpsFirst`].
+    pub fn security_policy(mut self, policy: SecurityPolicy) -> Self {
+        self.security_policy = policy;
+
+        self
+    }
+
+    /// How often a lookup is retried when it fails with a transient error, by default lookups are not retried.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+
+        self
+    }
+
+    /// Caches the outcome of every source, so repeated lookups of the same domain do not have to query it again. Nothing is cached by default.
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+
+        self
+    }
+
+    /// Configs that are returned for the domains they list without querying any other source, to support internal domains or override wrong configs.
+    pub fn registry(mut self, registry: ProviderRegistry) -> Self {
+        self.registry = Some(Arc::new(registry));
+
+        self
+    }
+
+    /// The offline snapshot of the ISPDB to consult, which defaults to the embedded ISPDB if the `bundled-ispdb` feature is enabled and to none otherwise.
+    pub fn snapshot(mut self, snapshot: Snapshot) -> Self {
+        self.snapshot = Some(Arc::new(snapshot));
+
+        self
+    }
+
+    /// Whether the snapshot is consulted before or after the network sources, defaults to [`SnapshotMode::Fallback`].
+    pub fn snapshot_mode(mut self, mode: SnapshotMode) -> Self {
+        self.snapshot_mode = mode;
+
+        self
+    }
+
+    /// The verifier used to probe servers when guessing a config, see [`Mechanism::Guess`].
+    #[cfg(feature = "verify")]
+    pub fn verifier(mut self, verifier: Verifier) -> Self {
+        self.verifier = verifier;
+
+        self
+    }
+
+    /// Creates a client that uses the default http client and DNS resolver.
+    #[cfg(feature = "http-curl")]
+    pub async fn build(self) -> Result<Client> {
+        let http = Http::with_options(self.timeout, self.user_agent.as_deref())?;
+        let dns = Dns::with_runtime(
+            self.resolver_config.clone(),
+            self.resolver_opts,
+            self.runtime.clone().unwrap_or_default(),
+        )
+        .await?;
+
+        Ok(self.build_with(http, dns))
+    }
+
+    /// Creates a client that uses the given http client and DNS resolver.
+    ///
+    /// The timeout, user agent, resolver configuration and runtime are not used, as they only apply to the default implementations.
+    pub fn build_with<H: HttpTransport, R: DnsResolver>(self, http: H, dns: R) -> Client<H, R> {
+        Client {
+            http,
+            dns,
+            ispdb_url: self.ispdb_url,
+            strategy: self.strategy,
+            disabled: self.disabled,
+            send_email_address: self.send_email_address,
+            security_policy: self.security_policy,
+            retry_policy: self.retry_policy,
+            cache: self.cache,
+            registry: self.registry,
+            snapshot: self.snapshot,
+            snapshot_mode: self.snapshot_mode,
+            #[cfg(feature = "verify")]
+            verifier: self.verifier,
+        }
+    }
+}
+
+/// Adds the `emailaddress` query parameter, as described in Thunderbird's autoconfig specification, to a url.
+fn with_email_address(url: &str, email_address: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut url) => {
+            url.query_pairs_mut()
+                .append_pair("emailaddress", email_address);
+
+            url.to_string()
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Removes the `emailaddress` query parameter from a url.
+fn without_email_address(url: &str) -> String {
+    let mut url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return url.to_string(),
+    };
+
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| name != "emailaddress")
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    url.to_string()
+}
+
+/// Whether every server in the config is hosted under the domain, or under the base domain of one of the domain's mail exchangers.
+fn hostnames_match(domain: &str, config: &Config, exchanges: &[String]) -> bool {
+    let hostnames: Vec<String> = config
+        .email_provider()
+        .servers()
+        .into_iter()
+        .filter_map(|server| server.hostname())
+        .map(|hostname| hostname.replace("%EMAILDOMAIN%", domain))
+        .collect();
+
+    let mut outside: Vec<&String> = hostnames
+        .iter()
+        .filter(|hostname| !utils::is_within_domain(hostname, domain))
+        .collect();
+
+    if outside.is_empty() {
+        return true;
+    }
+
+    outside.retain(|hostname| {
+        !exchanges
+            .iter()
+            .any(|exchange| utils::is_within_domain(hostname, utils::base_domain(exchange)))
+    });
+
+    outside.is_empty()
+}
+
+fn with_diagnostics(mut config: Config, domain: &str, exchanges: &[String]) -> Config {
+    let diagnostics = config.validate_with_mx(domain, exchanges);
+
+    config.set_diagnostics(diagnostics);
+
+    config
+}
+
+/// The mail exchangers of a domain, most preferred first, which are looked up once per lookup and shared by the sources that need them.
+#[derive(Default)]
+struct Exchanges {
+    hostnames: Vec<String>,
+    ttl: Option<Duration>,
+    error: Mutex<Option<Error>>,
+}
+
+impl Exchanges {
+    fn new(result: Result<(Vec<String>, Option<Duration>)>) -> Self {
+        match result {
+            Ok((hostnames, ttl)) => Self {
+                hostnames,
+                ttl,
+                error: Mutex::new(None),
+            },
+            Err(error) => Self {
+                error: Mutex::new(Some(error)),
+                ..Self::default()
+            },
+        }
+    }
+
+    /// The most preferred mail exchanger along with the TTL of its record.
+    ///
+    /// If the exchangers could not be looked up, the error is only returned the first time, as it can only be reported by one source.
+    fn preferred(&self) -> Result<(&str, Option<Duration>)> {
+        if let Some(error) = self.error.lock().unwrap().take() {
+            return Err(error);
+        }
+
+        match self.hostnames.first() {
+            Some(hostname) => Ok((hostname, self.ttl)),
+            None => Err(Error::new(
+                ErrorKind::NoRecordsFound,
+                "Domain does not have any mail exchangers",
+            )),
+        }
+    }
+}
+
+/// A single discovery source that has yet to be awaited.
+struct Lookup<'a> {
+    source: Source,
+    future: BoxFuture<'a, Result<Config>>,
+}
+
+impl<'a> Lookup<'a> {
+    /// Creates a lookup, errors returned by the future are tagged with the source and how long it took.
+    fn new(source: Source, future: BoxFuture<'a, Result<Config>>) -> Self {
+        let origin = source.clone();
+
+        let future = async move {
+            let start = Instant::now();
+
+            future
+                .await
+                .map_err(|error| error.with_origin(origin, start.elapsed()))
+        };
+
+        Self {
+            source,
+            future: future.boxed(),
+        }
+    }
+
+    /// Attaches the diagnostics of validating the config against the domain and its mail exchangers, once the lookup finishes.
+    fn validated(self, domain: &'a str, exchanges: &'a [String]) -> Self {
+        let future = self
+            .future
+            .map(move |result| result.map(|config| with_diagnostics(config, domain, exchanges)));
+
+        Self::new(self.source, future.boxed())
+    }
+
+    /// Awaits the lookup, keeping track of how long it took.
+    async fn into_candidate(self) -> Candidate {
+        let start = Instant::now();
+
+        let result = self.future.await;
+
+        Candidate::new(self.source, start.elapsed(), result)
+    }
+}
+
+/// Awaits the lookups and returns the first config that is found, or the errors of every lookup if none was.
+async fn first_to_finish(lookups: Vec<Lookup<'_>>) -> std::result::Result<Config, Vec<Error>> {
+    let mut pending: FuturesUnordered<_> =
+        lookups.into_iter().map(|lookup| lookup.future).collect();
+
+    let mut errors = Vec::new();
+
+    while let Some(result) = pending.next().await {
+        match result {
+            Ok(config) => return Ok(config),
+            Err(error) => errors.push(error),
+        }
+    }
 
-use crate::{config::Config, dns::Dns, error::Result, http::Http, parse};
+    Err(errors)
+}
+
+/// Awaits the lookups and returns the config of the highest priority source, giving higher priority sources a grace period to finish once any config was found.
+///
+/// Lookups with the same [`Mechanism`] are prioritized by their order in the given list, so the outcome does not depend on which source responds first.
+async fn first_by_priority(
+    lookups: Vec<Lookup<'_>>,
+    grace: Duration,
+) -> std::result::Result<Config, Vec<Error>> {
+    let mut pending_keys = Vec::new();
+
+    let mut pending: FuturesUnordered<_> = lookups
+        .into_iter()
+        .enumerate()
+        .map(|(index, lookup)| {
+            let key = (*lookup.source.mechanism(), index);
+
+            pending_keys.push(key);
+
+            lookup.future.map(move |result| (key, result))
+        })
+        .collect();
+
+    let mut best: Option<((Mechanism, usize), Config)> = None;
+    let mut deadline: Option<Delay> = None;
+    let mut errors = Vec::new();
+
+    loop {
+        let next = match deadline.as_mut() {
+            Some(delay) => match select(pending.next(), delay).await {
+                Either::Left((next, _)) => next,
+                // The grace period is over, so we settle for the best config we have.
+                Either::Right(_) => break,
+            },
+            None => pending.next().await,
+        };
+
+        let (key, result) = match next {
+            Some(next) => next,
+            None => break,
+        };
+
+        pending_keys.retain(|pending_key| pending_key != &key);
+
+        match result {
+            Ok(config) => {
+                if best.as_ref().map_or(true, |(best_key, _)| &key < best_key) {
+                    best = Some((key, config))
+                }
+            }
+            Err(error) => errors.push(error),
+        }
+
+        if let Some((best_key, _)) = &best {
+            // No source that is still running could beat the config we have.
+            if pending_keys
+                .iter()
+                .all(|pending_key| pending_key > best_key)
+            {
+                break;
+            }
+
+            if deadline.is_none() {
+                deadline = Some(Delay::new(grace));
+            }
+        }
+    }
 
-pub struct Client {
-    http: Http,
-    dns: Dns,
+    match best {
+        Some((_, config)) => Ok(config),
+        None => Err(errors),
+    }
 }
 
+#[cfg(feature = "http-curl")]
 impl Client {
+    /// Creates a client that uses the default http client and DNS resolver, with the default options.
     pub async fn new() -> Result<Self> {
-        let http = Http::new()?;
-        let dns = Dns::new().await?;
-        let client = Self { http, dns };
+        ClientBuilder::default().build().await
+    }
 
-        Ok(client)
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
     }
+}
 
-    pub async fn get_config<U: AsRef<str>>(&self, url: U) -> Result<Config> {
-        let bytes = self.http.get(url).await?;
+impl<H: HttpTransport, R: DnsResolver> Client<H, R> {
+    /// Creates a client that uses the given http client and DNS resolver, with the default options.
+    pub fn with_transports(http: H, dns: R) -> Self {
+        ClientBuilder::default().build_with(http, dns)
+    }
 
-        let config = parse::from_bytes(bytes)?;
+    fn is_enabled(&self, mechanism: Mechanism) -> bool {
+        !self.disabled.contains(&mechanism)
+    }
 
-        Ok(config)
+    /// Creates a lookup for a source of the domain, which is answered from the cache if the client has one and the source's outcome is cached.
+    fn lookup<'a>(
+        &'a self,
+        domain: &'a str,
+        source: Source,
+        future: BoxFuture<'a, Result<Config>>,
+    ) -> Lookup<'a> {
+        self.lookup_with_key(Key::new(domain, source), future)
     }
 
-    const TXT_RECORD_REGEX: &str = r"^mailconf=(https?://\S+)$";
+    /// Creates a lookup that is cached under the given key, for sources whose outcome depends on more than the domain.
+    fn lookup_with_key<'a>(
+        &'a self,
+        key: Key,
+        future: BoxFuture<'a, Result<Config>>,
+    ) -> Lookup<'a> {
+        let source = key.source().clone();
 
-    pub async fn get_url_from_txt<N: AsRef<str>>(&self, name: N) -> Result<Vec<String>> {
-        let records = self.dns.get_txt(name).await?;
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return Lookup::new(source, future),
+        };
 
-        let re = Regex::new(Self::TXT_RECORD_REGEX).unwrap();
+        let future = async move {
+            if let Some(result) = cache.get(&key).await {
+                return result;
+            }
 
-        let mut urls = Vec::new();
+            let result = future.await;
 
-        for record in records {
-            if let Some(record_str) = std::str::from_utf8(&record).ok() {
-                if let Some(captured) = re.captures(record_str) {
-                    if let Some(r#match) = captured.get(1) {
-                        let url = r#match.as_str();
+            cache.set(&key, &result).await;
 
-                        if let Some(url_parsed) = Url::parse(url).ok() {
-                            if url_parsed.scheme() == "https" {
-                                urls.push(url.to_string())
-                            }
-                        }
+            result
+        };
+
+        Lookup::new(source, future.boxed())
+    }
+
+    /// Sends a request and returns the response, if the request was successful.
+    async fn fetch(&self, request: Request) -> Result<Response> {
+        let response = self.http.send(request).await?;
+
+        // If we got an error response we return an error
+        if !response.is_success() {
+            return Err(Error::new(
+                ErrorKind::InvalidResponse,
+                format!("Http request failed with status {}", response.status()),
+            )
+            .with_status(response.status()));
+        }
+
+        Ok(response)
+    }
+
+    /// Creates the lookups for all of the sources that can be queried concurrently.
+    ///
+    /// Sources that already failed while creating the lookups, like a failing TXT record query, are returned as candidates.
+    ///
+    /// If a `query_address` is given, it is sent along to the autoconfig urls that are hosted by the provider and to the Autodiscover services, which are asked for the `postmaster` of the domain otherwise.
+    async fn lookups<'a>(
+        &'a self,
+        domain: &'a str,
+        query_address: Option<&str>,
+        exchanges: &'a Exchanges,
+    ) -> (Vec<Lookup<'a>>, Vec<Candidate>) {
+        let mut failed = Vec::new();
+
+        let urls = vec![
+            // Try connect to connect with the users mail server directly
+            Source::new(
+                Mechanism::Provider,
+                format!("https://autoconfig.{}/mail/config-v1.1.xml", domain),
+            ),
+            // The fallback url
+            Source::new(
+                Mechanism::WellKnown,
+                format!(
+                    "https://{}/.well-known/autoconfig/mail/config-v1.1.xml",
+                    domain
+                ),
+            ),
+            // If the previous two methods did not work then the email server provider has not setup Thunderbird autoconfig, so we ask Mozilla for their config.
+            Source::new(Mechanism::Ispdb, self.ispdb_url(domain)),
+        ];
+
+        let mut urls: Vec<Source> = urls
+            .into_iter()
+            .filter(|source| self.is_enabled(*source.mechanism()))
+            .collect();
+
+        if self.is_enabled(Mechanism::TxtRecord) {
+            let start = Instant::now();
+
+            match self.get_url_from_txt(domain).await {
+                Ok(txt_urls) => {
+                    for url in txt_urls {
+                        urls.push(Source::new(Mechanism::TxtRecord, url))
                     }
                 }
+                Err(error) => {
+                    let source = Source::new(Mechanism::TxtRecord, domain);
+                    let elapsed = start.elapsed();
+
+                    failed.push(Candidate::new(
+                        source.clone(),
+                        elapsed,
+                        Err(error.with_origin(source, elapsed)),
+                    ))
+                }
+            };
+        }
+
+        if let Some(query_address) = query_address {
+            urls = urls
+                .into_iter()
+                .map(|source| match source.mechanism() {
+                    Mechanism::Ispdb => source,
+                    mechanism => Source::new(
+                        *mechanism,
+                        with_email_address(source.location(), query_address),
+                    ),
+                })
+                .collect();
+        }
+
+        let mut lookups: Vec<Lookup> = Vec::new();
+
+        for source in urls {
+            if lookups
+                .iter()
+                .any(|lookup| lookup.source.location() == source.location())
+            {
+                continue;
             }
+
+            // Only the well known urls fall back to plain http, the ISPDB and urls published in TXT records must use https.
+            let future = match source.mechanism() {
+                Mechanism::Provider | Mechanism::WellKnown => self
+                    .get_provider_config(domain, source.location().to_string(), exchanges)
+                    .boxed(),
+                _ => self.get_config(source.location().to_string()).boxed(),
+            };
+
+            lookups.push(self.lookup(domain, source, future));
         }
 
-        Ok(urls)
+        // Autodiscover servers need an email address to respond to, so unless the address of the user may be sent we ask for the postmaster, which every domain should have.
+        let email_address = match query_address {
+            Some(query_address) => query_address.to_string(),
+            None => format!("{}{}{}", POSTMASTER, AT_SYMBOL, domain),
+        };
+
+        // Microsoft Exchange and Office 365 do not support Thunderbird's autoconfig, so we also ask their Autodiscover service.
+        if self.is_enabled(Mechanism::Autodiscover) {
+            for url in autodiscover::urls(domain) {
+                let future =
+                    self.get_autodiscover_config(url.clone(), domain, email_address.clone());
+
+                // The settings are specific to the account, e.g. its login name, so they are cached per email address.
+                let key = Key::new(domain, Source::new(Mechanism::Autodiscover, url))
+                    .with_email_address(&email_address);
+
+                lookups.push(self.lookup_with_key(key, future.boxed()));
+            }
+        }
+
+        // Office 365 knows the servers of the domains it hosts, even when those domains do not publish an Autodiscover service.
+        if self.is_enabled(Mechanism::AutodiscoverV2) {
+            lookups.push(
+                self.lookup(
+                    domain,
+                    Source::new(
+                        Mechanism::AutodiscoverV2,
+                        autodiscover_v2::location(&email_address),
+                    ),
+                    async move {
+                        self.get_autodiscover_v2_config(domain, email_address, exchanges)
+                            .await
+                    }
+                    .boxed(),
+                ),
+            );
+        }
+
+        // Some providers do not host a config file, but do publish which servers to use in their DNS records.
+        if self.is_enabled(Mechanism::Srv) {
+            lookups.push(self.lookup(
+                domain,
+                Source::new(Mechanism::Srv, domain),
+                self.get_config_from_srv(domain, exchanges).boxed(),
+            ));
+        }
+
+        (lookups, failed)
+    }
+
+    /// Given an email providers domain, query the enabled discovery sources for that provider and return the config that was found.
+    ///
+    /// When multiple sources return a config, the client's [`Strategy`] decides which one is returned.
+    ///
+    /// Internationalized domains are converted to their ASCII form first, see [`address::domain_to_ascii`]. Names without a top level domain, like `corp`, are only looked up in the registry.
+    pub async fn lookup_domain<D: AsRef<str>>(&self, domain: D) -> Result<Config> {
+        let domain = match address::domain_to_ascii(&domain) {
+            Ok(domain) => domain,
+            Err(error) => {
+                // Internal names without a top level domain, like `corp`, can only be known to the registry.
+                let registered = address::hostname_to_ascii(domain)
+                    .ok()
+                    .and_then(|domain| Some((self.registered_config(&domain)?, domain)));
+
+                return match registered {
+                    Some((config, domain)) => {
+                        let exchanges = self.exchanges(&domain).await;
+
+                        Ok(with_diagnostics(config, &domain, &exchanges.hostnames))
+                    }
+                    None => Err(error),
+                };
+            }
+        };
+
+        self.discover(domain, None).await
+    }
+
+    /// Given an email address, query the enabled discovery sources for the email provider and return the config that was found.
+    ///
+    /// When multiple sources return a config, the client's [`Strategy`] decides which one is returned.
+    ///
+    /// The address is parsed as described in RFC 5321 and RFC 6531, see [`EmailAddress::parse`].
+    pub async fn lookup_addr<E: AsRef<str>>(&self, email_address: E) -> Result<Config> {
+        let address = EmailAddress::parse(email_address)?;
+
+        // The servers we query only know the ASCII form of the domain.
+        let email_address = address.to_string();
+
+        let query_address = if self.send_email_address {
+            Some(email_address.as_str())
+        } else {
+            None
+        };
+
+        self.discover(address.domain(), query_address).await
+    }
+
+    /// Finds the config for a domain and validates it against that domain, retrying transient failures as allowed by the [`RetryPolicy`].
+    async fn discover<D: AsRef<str>>(
+        &self,
+        domain: D,
+        query_address: Option<&str>,
+    ) -> Result<Config> {
+        let mut retry = 0;
+
+        loop {
+            // Several sources and the validation need the mail exchangers, so they are only looked up once per attempt.
+            let exchanges = self.exchanges(domain.as_ref()).await;
+
+            match self.find(domain.as_ref(), query_address, &exchanges).await {
+                Ok(config) => {
+                    return Ok(with_diagnostics(
+                        config,
+                        domain.as_ref(),
+                        &exchanges.hostnames,
+                    ));
+                }
+                Err(error) if error.is_transient() && retry < self.retry_policy.retries() => {
+                    Delay::new(self.retry_policy.delay(retry)).await;
+
+                    retry += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    async fn find<D: AsRef<str>>(
+        &self,
+        domain: D,
+        query_address: Option<&str>,
+        exchanges: &Exchanges,
+    ) -> Result<Config> {
+        // Configs provided by the application override every other source.
+        if let Some(config) = self.registered_config(domain.as_ref()) {
+            return Ok(config);
+        }
+
+        // The snapshot does not need the network, so it can answer before any source is queried.
+        if self.snapshot_mode == SnapshotMode::First && self.is_enabled(Mechanism::Snapshot) {
+            if let Some(snapshot) = &self.snapshot {
+                if let Some(config) = snapshot.get_by_domain(domain.as_ref()) {
+                    return Ok(config.clone());
+                }
+            }
+        }
+
+        let (lookups, failed) = self
+            .lookups(domain.as_ref(), query_address, exchanges)
+            .await;
+
+        let mut errors: Vec<_> = failed
+            .into_iter()
+            .filter_map(|candidate| candidate.into_result().err())
+            .collect();
+
+        match self.strategy {
+            Strategy::Fastest => match first_to_finish(lookups).await {
+                Ok(config) => return Ok(config),
+                Err(lookup_errors) => errors.extend(lookup_errors),
+            },
+            Strategy::Priority { grace } => match first_by_priority(lookups, grace).await {
+                Ok(config) => return Ok(config),
+                Err(lookup_errors) => errors.extend(lookup_errors),
+            },
+        }
+
+        // Custom domains at large providers rarely host a config themselves, so we look up who handles their mail.
+        if self.is_enabled(Mechanism::Mx) {
+            let lookup = self.lookup(
+                domain.as_ref(),
+                Source::new(Mechanism::Mx, domain.as_ref()),
+                self.get_config_from_mx(domain.as_ref(), exchanges).boxed(),
+            );
+
+            match lookup.future.await {
+                Ok(config) => return Ok(config),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        // The ISPDB may be unreachable, for example behind a restrictive proxy, so we fall back to the snapshot.
+        if self.snapshot.is_some() && self.is_enabled(Mechanism::Snapshot) {
+            let lookup = Lookup::new(
+                Source::new(Mechanism::Snapshot, domain.as_ref()),
+                self.get_config_from_snapshot(domain.as_ref(), exchanges)
+                    .boxed(),
+            );
+
+            match lookup.future.await {
+                Ok(config) => return Ok(config),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        // As a last resort, try the hostnames and ports that mail servers commonly use.
+        #[cfg(feature = "verify")]
+        if self.is_enabled(Mechanism::Guess) {
+            let lookup = self.lookup(
+                domain.as_ref(),
+                Source::new(Mechanism::Guess, domain.as_ref()),
+                guess::guess(domain.as_ref(), &self.verifier).boxed(),
+            );
+
+            match lookup.future.await {
+                Ok(config) => return Ok(config),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        Err(Error::new(
+            ErrorKind::NotFound(errors),
+            "Could not find a valid config",
+        ))
+    }
+
+    /// Queries all of the enabled sources for a domain, waits for every one of them to finish and returns their outcomes ordered by [`Candidate::rank`].
+    pub async fn discover_all<D: AsRef<str>>(&self, domain: D) -> Vec<Candidate> {
+        // An invalid domain is queried as given, so every source reports why it failed.
+        let domain = address::domain_to_ascii(domain.as_ref())
+            .unwrap_or_else(|_| domain.as_ref().to_string());
+        let domain = domain.as_str();
+
+        let exchanges = self.exchanges(domain).await;
+
+        let (mut lookups, mut candidates) = self.lookups(domain, None, &exchanges).await;
+
+        if self.registry.is_some() && self.is_enabled(Mechanism::Registry) {
+            lookups.push(Lookup::new(
+                Source::new(Mechanism::Registry, domain),
+                self.get_config_from_registry(domain).boxed(),
+            ));
+        }
+
+        if self.is_enabled(Mechanism::Mx) {
+            lookups.push(self.lookup(
+                domain,
+                Source::new(Mechanism::Mx, domain),
+                self.get_config_from_mx(domain, &exchanges).boxed(),
+            ));
+        }
+
+        if self.snapshot.is_some() && self.is_enabled(Mechanism::Snapshot) {
+            lookups.push(Lookup::new(
+                Source::new(Mechanism::Snapshot, domain),
+                self.get_config_from_snapshot(domain, &exchanges).boxed(),
+            ));
+        }
+
+        #[cfg(feature = "verify")]
+        if self.is_enabled(Mechanism::Guess) {
+            lookups.push(self.lookup(
+                domain,
+                Source::new(Mechanism::Guess, domain),
+                guess::guess(domain, &self.verifier).boxed(),
+            ));
+        }
+
+        let lookups = lookups.into_iter().map(|lookup| {
+            lookup
+                .validated(domain, &exchanges.hostnames)
+                .into_candidate()
+        });
+
+        candidates.extend(join_all(lookups).await);
+
+        candidates.sort_by(Candidate::rank);
+
+        candidates
+    }
+
+    /// The url at which the ISPDB stores the config for a given domain.
+    pub(crate) fn ispdb_url<D: AsRef<str>>(&self, domain: D) -> String {
+        format!("{}{}", self.ispdb_url, domain.as_ref())
+    }
+
+    pub(crate) async fn get_config<U: AsRef<str>>(&self, url: U) -> Result<Config> {
+        let response = self.fetch(Request::get(url.as_ref())).await?;
+
+        let max_age = cache::max_age(&response);
+
+        let mut config = parse::from_bytes(response.into_body())?;
+
+        config.limit_max_age(max_age);
+
+        if url.as_ref().starts_with("https://") {
+            config.set_trust(Trust::Secure);
+        } else {
+            config.set_trust(Trust::Insecure);
+        }
+
+        Ok(config)
+    }
+
+    /// Fetches a config that is hosted by the provider, falling back from https to plain http as far as the [`SecurityPolicy`] allows.
+    async fn get_provider_config<D: AsRef<str>, U: AsRef<str>>(
+        &self,
+        domain: D,
+        url: U,
+        exchanges: &Exchanges,
+    ) -> Result<Config> {
+        let url = url.as_ref();
+
+        let path = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))
+            .unwrap_or(url);
+
+        let https_error = match self.get_config(format!("https://{}", path)).await {
+            Ok(config) => return Ok(config),
+            Err(error) => error,
+        };
+
+        if self.security_policy == SecurityPolicy::HttpsOnly {
+            return Err(https_error);
+        }
+
+        // The address of the user is never sent over plain http.
+        let http_url = without_email_address(&format!("http://{}", path));
+
+        let mut config = match self.get_config(http_url).await {
+            Ok(config) => config,
+            Err(http_error) => {
+                return Err(Error::new(
+                    ErrorKind::NotFound(vec![https_error, http_error]),
+                    "Config could not be fetched over https or plain http",
+                ))
+            }
+        };
+
+        if hostnames_match(domain.as_ref(), &config, &exchanges.hostnames) {
+            config.set_trust(Trust::MatchingHostnames);
+        } else if self.security_policy == SecurityPolicy::MatchingHostnames {
+            return Err(Error::new(
+                ErrorKind::Insecure,
+                "Config was fetched over plain http and contains servers outside of the domain",
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// The mail exchangers of a domain, which are cached for as long as their records allow if the client has a cache.
+    async fn exchanges(&self, domain: &str) -> Exchanges {
+        let key = Key::new(domain, Source::new(Mechanism::Mx, domain)).with_record_type("MX");
+
+        let query = async move {
+            let mut records = self.dns.mx(domain).await?;
+
+            // A "null MX" record (RFC 7505) signals that the domain does not accept email at all.
+            records.retain(|mx| !mx.exchange().trim_end_matches('.').is_empty());
+            records.sort_by_key(|mx| mx.preference());
+
+            let ttl = records.iter().filter_map(|mx| mx.ttl()).min();
+
+            let hostnames = records
+                .iter()
+                .map(|mx| mx.exchange().trim_end_matches('.').to_string())
+                .collect();
+
+            Ok((hostnames, ttl))
+        };
+
+        Exchanges::new(self.cached_records(key, query.boxed()).await)
+    }
+
+    /// Answers a DNS query from the cache if the client has one and the answer is cached.
+    async fn cached_records(
+        &self,
+        key: Key,
+        query: BoxFuture<'_, Result<(Vec<String>, Option<Duration>)>>,
+    ) -> Result<(Vec<String>, Option<Duration>)> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return query.await,
+        };
+
+        if let Some(result) = cache.get_records(&key).await {
+            return result;
+        }
+
+        let result = query.await;
+
+        cache.set_records(&key, &result).await;
+
+        result
+    }
+
+    const MAX_AUTODISCOVER_REDIRECTS: usize = 3;
+
+    /// Requests the settings for the given email address from a Microsoft Autodiscover server, following any redirects it returns.
+    pub(crate) async fn get_autodiscover_config<U: AsRef<str>, D: AsRef<str>, E: AsRef<str>>(
+        &self,
+        url: U,
+        domain: D,
+        email_address: E,
+    ) -> Result<Config> {
+        let mut url = url.as_ref().to_string();
+        let mut email_address = email_address.as_ref().to_string();
+
+        for _ in 0..=Self::MAX_AUTODISCOVER_REDIRECTS {
+            let request = Request::post(
+                &url,
+                "text/xml; charset=utf-8",
+                autodiscover::request_body(&email_address),
+            );
+
+            let response = self.fetch(request).await?;
+
+            let max_age = cache::max_age(&response);
+
+            match autodiscover::from_bytes(response.into_body(), domain.as_ref(), &email_address)? {
+                Action::Settings(mut config) => {
+                    config.set_trust(Trust::Secure);
+                    config.limit_max_age(max_age);
+
+                    return Ok(config);
+                }
+                Action::RedirectUrl(redirect_url) => {
+                    // Never allow a redirect to downgrade the connection to plain http
+                    if Url::parse(&redirect_url)
+                        .ok()
+                        .map(|url| url.scheme() == "https")
+                        != Some(true)
+                    {
+                        return Err(Error::new(
+                            ErrorKind::InvalidResponse,
+                            "Autodiscover server redirected to an insecure url",
+                        ));
+                    }
+
+                    url = redirect_url
+                }
+                Action::RedirectAddr(redirect_address) => email_address = redirect_address,
+            }
+        }
+
+        Err(Error::new(
+            ErrorKind::InvalidResponse,
+            "Autodiscover server redirected too many times",
+        ))
+    }
+
+    /// Requests the endpoint of every supported protocol from the Office 365 Autodiscover v2 service and creates a config from the ones that were returned.
+    ///
+    /// The service is only asked about domains whose mail exchangers are run by Office 365, as it also answers for domains it does not host.
+    async fn get_autodiscover_v2_config<D: AsRef<str>, E: AsRef<str>>(
+        &self,
+        domain: D,
+        email_address: E,
+        exchanges: &Exchanges,
+    ) -> Result<Config> {
+        if !autodiscover_v2::is_hosted(&exchanges.hostnames) {
+            return Err(Error::new(
+                ErrorKind::NotFound(Vec::new()),
+                "Mail exchangers of the domain are not run by Office 365",
+            ));
+        }
+
+        let requests = autodiscover_v2::PROTOCOLS.iter().map(|protocol| {
+            let url = autodiscover_v2::url(email_address.as_ref(), protocol);
+
+            async move {
+                let response = self.fetch(Request::get(url)).await?;
+
+                let max_age = cache::max_age(&response);
+
+                let endpoint = autodiscover_v2::from_bytes(response.into_body())?;
+
+                Ok::<_, Error>(((*protocol, endpoint), max_age))
+            }
+        });
+
+        let mut endpoints = Vec::new();
+        let mut max_ages = Vec::new();
+        let mut first_error = None;
+
+        for result in join_all(requests).await {
+            match result {
+                Ok((endpoint, max_age)) => {
+                    endpoints.push(endpoint);
+                    max_ages.push(max_age);
+                }
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+
+        if let Some(error) = first_error.filter(|_| endpoints.is_empty()) {
+            return Err(error);
+        }
+
+        let mut config = autodiscover_v2::into_config(domain, endpoints)?;
+
+        config.set_trust(Trust::Secure);
+
+        for max_age in max_ages {
+            config.limit_max_age(max_age);
+        }
+
+        Ok(config)
+    }
+
+    /// Looks up the RFC 6186 SRV records for a domain and creates a config from the services that were found.
+    async fn get_config_from_srv<D: AsRef<str>>(
+        &self,
+        domain: D,
+        exchanges: &Exchanges,
+    ) -> Result<Config> {
+        let domain = domain.as_ref();
+
+        let lookups = srv::SERVICES.iter().map(|service| async move {
+            let name = srv::record_name(service, domain);
+
+            // A service that can't be resolved is simply not offered by this domain.
+            let records = match self.dns.srv(&name).await {
+                Ok(mut records) => {
+                    srv::sort(&mut records);
+
+                    records
+                }
+                Err(_) => Vec::new(),
+            };
+
+            (*service, records)
+        });
+
+        let services = join_all(lookups).await;
+
+        let mut config = srv::into_config(domain, services)?;
+
+        // DNS responses are not authenticated, so the records are only as trustworthy as the servers they point to.
+        if hostnames_match(domain, &config, &exchanges.hostnames) {
+            config.set_trust(Trust::MatchingHostnames);
+        } else {
+            config.set_trust(Trust::Insecure);
+        }
+
+        Ok(config)
+    }
+
+    /// Looks up the most preferred mail exchanger for a domain and asks the ISPDB for the config of the provider that hosts it.
+    ///
+    /// This is what Thunderbird does to find the config for custom domains hosted by large providers, e.g. `aspmx.l.google.com` leads to `google.com`.
+    async fn get_config_from_mx<D: AsRef<str>>(
+        &self,
+        domain: D,
+        exchanges: &Exchanges,
+    ) -> Result<Config> {
+        let (exchange, ttl) = exchanges.preferred()?;
+
+        let mut mx_domains = Vec::new();
+
+        // Try the mail exchanger without its own hostname label first, e.g. `mail.protection.outlook.com`, then its base domain.
+        if let Some((_, parent)) = exchange.split_once('.') {
+            mx_domains.push(parent);
+        }
+
+        mx_domains.push(utils::base_domain(exchange));
+
+        mx_domains.dedup();
+        mx_domains.retain(|mx_domain| {
+            mx_domain.contains('.') && !mx_domain.eq_ignore_ascii_case(domain.as_ref())
+        });
+
+        if mx_domains.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound(Vec::new()),
+                "Mail exchanger is hosted on the domain itself",
+            ));
+        }
+
+        let futures = mx_domains
+            .into_iter()
+            .map(|mx_domain| self.get_config(self.ispdb_url(mx_domain)).boxed());
+
+        let (mut config, _remaining) = select_ok(futures).await?;
+
+        config.limit_max_age(ttl);
+
+        Ok(config)
+    }
+
+    /// Looks up a domain in the registry of configs provided by the application.
+    fn registered_config(&self, domain: &str) -> Option<Config> {
+        if !self.is_enabled(Mechanism::Registry) {
+            return None;
+        }
+
+        self.registry
+            .as_ref()
+            .and_then(|registry| registry.get(domain))
+            .cloned()
+    }
+
+    pub(crate) async fn get_config_from_registry<D: AsRef<str>>(
+        &self,
+        domain: D,
+    ) -> Result<Config> {
+        self.registry
+            .as_ref()
+            .and_then(|registry| registry.get(domain.as_ref()))
+            .cloned()
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound(Vec::new()),
+                    "Registry does not contain a config for the domain",
+                )
+            })
+    }
+
+    /// Looks up a domain in the offline snapshot, and otherwise the provider that hosts its most preferred mail exchanger.
+    ///
+    /// If the mail exchangers could not be looked up, that is reported by the MX source.
+    async fn get_config_from_snapshot<D: AsRef<str>>(
+        &self,
+        domain: D,
+        exchanges: &Exchanges,
+    ) -> Result<Config> {
+        let snapshot = match &self.snapshot {
+            Some(snapshot) => snapshot,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotFound(Vec::new()),
+                    "Client does not have a snapshot",
+                ))
+            }
+        };
+
+        if let Some(config) = snapshot.get_by_domain(domain.as_ref()) {
+            return Ok(config.clone());
+        }
+
+        match exchanges
+            .hostnames
+            .first()
+            .and_then(|exchange| snapshot.get_by_mx(exchange))
+        {
+            Some(config) => Ok(config.clone()),
+            None => Err(Error::new(
+                ErrorKind::NotFound(Vec::new()),
+                "Snapshot does not contain a config for the domain or its mail exchanger",
+            )),
+        }
+    }
+
+    const TXT_RECORD_REGEX: &str = r"^mailconf=(https?://\S+)$";
+
+    /// The https urls published in the `mailconf` TXT records of a name, which are cached for as long as the records allow if the client has a cache.
+    pub(crate) async fn get_url_from_txt<N: AsRef<str>>(&self, name: N) -> Result<Vec<String>> {
+        let name = name.as_ref();
+
+        let key = Key::new(name, Source::new(Mechanism::TxtRecord, name)).with_record_type("TXT");
+
+        let query = async move {
+            let records = self.dns.txt(name).await?;
+
+            let ttl = records.iter().filter_map(|record| record.ttl()).min();
+
+            let re = Regex::new(Self::TXT_RECORD_REGEX).unwrap();
+
+            let mut urls = Vec::new();
+
+            for record in records {
+                if let Ok(record_str) = std::str::from_utf8(record.data()) {
+                    if let Some(captured) = re.captures(record_str) {
+                        if let Some(r#match) = captured.get(1) {
+                            let url = r#match.as_str();
+
+                            if let Ok(url_parsed) = Url::parse(url) {
+                                if url_parsed.scheme() == "https" {
+                                    urls.push(url.to_string())
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            Ok((urls, ttl))
+        };
+
+        let (urls, _ttl) = self.cached_records(key, query.boxed()).await?;
+
+        Ok(urls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering::SeqCst},
+            Mutex,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use futures::{executor::block_on, FutureExt};
+    use futures_timer::Delay;
+
+    use crate::{
+        cache::Cache,
+        config::{Config, EmailProvider, ServerType},
+        discovery::{Mechanism, RetryPolicy, SecurityPolicy, Source, Strategy, Trust},
+        error::{Error, ErrorKind, Result},
+        ispdb::{Snapshot, SnapshotMode},
+        registry::ProviderRegistry,
+        transport::{
+            DnsResolver, HttpTransport, MxRecord, Request, Response, SrvRecord, TxtRecord,
+        },
+        validate::Diagnostic,
+    };
+
+    use super::{first_by_priority, first_to_finish, Client, ClientBuilder, Exchanges, Lookup};
+
+    struct MockHttp {
+        responses: HashMap<&'static str, &'static str>,
+    }
+
+    #[async_trait]
+    impl HttpTransport for MockHttp {
+        async fn send(&self, request: Request) -> Result<Response> {
+            match self.responses.get(request.url()) {
+                Some(body) => Ok(Response::new(200, body.as_bytes().to_vec())),
+                None => Ok(Response::new(404, "Not found")),
+            }
+        }
+    }
+
+    struct MockDns;
+
+    fn no_records() -> Error {
+        Error::new(ErrorKind::NoRecordsFound, "No records found")
+    }
+
+    #[async_trait]
+    impl DnsResolver for MockDns {
+        async fn txt(&self, _name: &str) -> Result<Vec<TxtRecord>> {
+            Err(no_records())
+        }
+
+        async fn srv(&self, _name: &str) -> Result<Vec<SrvRecord>> {
+            Err(no_records())
+        }
+
+        async fn mx(&self, _name: &str) -> Result<Vec<MxRecord>> {
+            Err(no_records())
+        }
+    }
+
+    const PROVIDER_CONFIG: &str = r#"
+        <clientConfig version="1.1">
+            <emailProvider id="provider">
+                <domain>example.com</domain>
+            </emailProvider>
+        </clientConfig>
+    "#;
+
+    const ISPDB_CONFIG: &str = r#"
+        <clientConfig version="1.1">
+            <emailProvider id="ispdb">
+                <domain>example.com</domain>
+            </emailProvider>
+        </clientConfig>
+    "#;
+
+    fn lookup(mechanism: Mechanism, id: &'static str, delay: Duration) -> Lookup<'static> {
+        let future = async move {
+            Delay::new(delay).await;
+
+            Result::Ok(Config::new(EmailProvider::new(id, Vec::new())))
+        };
+
+        Lookup::new(Source::new(mechanism, id), future.boxed())
+    }
+
+    fn failing_lookup(mechanism: Mechanism, delay: Duration) -> Lookup<'static> {
+        let future = async move {
+            Delay::new(delay).await;
+
+            Result::Err(Error::new(ErrorKind::NotFound(Vec::new()), "Not found"))
+        };
+
+        Lookup::new(Source::new(mechanism, "failing"), future.boxed())
+    }
+
+    #[test]
+    fn test_fastest() {
+        let lookups = vec![
+            failing_lookup(Mechanism::Provider, Duration::ZERO),
+            lookup(Mechanism::Ispdb, "ispdb", Duration::from_millis(50)),
+            lookup(Mechanism::Srv, "srv", Duration::from_secs(5)),
+        ];
+
+        let config = block_on(first_to_finish(lookups)).unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+
+        let lookups = vec![
+            failing_lookup(Mechanism::Provider, Duration::ZERO),
+            failing_lookup(Mechanism::Ispdb, Duration::from_millis(10)),
+            failing_lookup(Mechanism::Srv, Duration::from_millis(20)),
+        ];
+
+        let errors = block_on(first_to_finish(lookups)).unwrap_err();
+
+        assert_eq!(errors.len(), 3);
+    }
+
+    #[test]
+    fn test_priority_waits_for_better_source() {
+        let lookups = vec![
+            lookup(Mechanism::Ispdb, "ispdb", Duration::ZERO),
+            lookup(Mechanism::Provider, "provider", Duration::from_millis(50)),
+        ];
+
+        let config = block_on(first_by_priority(lookups, Duration::from_secs(5))).unwrap();
+
+        assert_eq!(config.email_provider().id(), "provider");
+    }
+
+    #[test]
+    fn test_priority_grace_period_expires() {
+        let lookups = vec![
+            lookup(Mechanism::Ispdb, "ispdb", Duration::ZERO),
+            lookup(Mechanism::Provider, "provider", Duration::from_secs(5)),
+        ];
+
+        let config = block_on(first_by_priority(lookups, Duration::from_millis(50))).unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+    }
+
+    #[test]
+    fn test_mock_transports() {
+        let mut responses = HashMap::new();
+
+        responses.insert(
+            "http://autoconfig.example.com/mail/config-v1.1.xml",
+            PROVIDER_CONFIG,
+        );
+        responses.insert(
+            "https://autoconfig.thunderbird.net/v1.1/example.com",
+            ISPDB_CONFIG,
+        );
+        responses.insert(
+            "https://autoconfig.thunderbird.net/v1.1/example.net",
+            ISPDB_CONFIG,
+        );
+
+        let client = Client::with_transports(MockHttp { responses }, MockDns);
+
+        let config = block_on(client.lookup_domain("example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "provider");
+
+        let candidates = block_on(client.discover_all("example.com"));
+
+        assert!(candidates[0].is_ok());
+        assert_eq!(candidates[0].source().mechanism(), &Mechanism::Provider);
+        assert!(candidates[1].is_ok());
+        assert_eq!(candidates[1].source().mechanism(), &Mechanism::Ispdb);
+        assert!(candidates[2..].iter().all(|candidate| !candidate.is_ok()));
+
+        // The ISPDB config only lists example.com, so it is flagged when used for another domain.
+        let config = block_on(client.lookup_domain("example.net")).unwrap();
+
+        assert_eq!(
+            config.diagnostics(),
+            [Diagnostic::DomainNotListed("example.net".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_builder_options() {
+        let mut responses = HashMap::new();
+
+        responses.insert(
+            "http://autoconfig.example.com/mail/config-v1.1.xml",
+            PROVIDER_CONFIG,
+        );
+        responses.insert("https://ispdb.example.org/example.com", ISPDB_CONFIG);
+
+        let client = ClientBuilder::new()
+            .ispdb_url("https://ispdb.example.org")
+            .disable(Mechanism::Provider)
+            .strategy(Strategy::Fastest)
+            .build_with(MockHttp { responses }, MockDns);
+
+        let config = block_on(client.lookup_addr("user@example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut snapshot = Snapshot::new();
+
+        snapshot
+            .insert(Config::from_xml(ISPDB_CONFIG.replace("\"ispdb\"", "\"snapshot\"")).unwrap());
+
+        let builder = ClientBuilder::new()
+            .snapshot(snapshot)
+            .disable(Mechanism::Guess);
+
+        // Without a network source that returns a config, the snapshot is used as a fallback.
+        let client = builder.clone().build_with(
+            MockHttp {
+                responses: HashMap::new(),
+            },
+            MockDns,
+        );
+
+        let config = block_on(client.lookup_domain("example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "snapshot");
+        assert_eq!(config.trust(), Some(&Trust::Secure));
+
+        let mut responses = HashMap::new();
+
+        responses.insert(
+            "https://autoconfig.thunderbird.net/v1.1/example.com",
+            ISPDB_CONFIG,
+        );
+
+        let client = builder.clone().build_with(
+            MockHttp {
+                responses: responses.clone(),
+            },
+            MockDns,
+        );
+
+        let config = block_on(client.lookup_domain("example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+
+        let client = builder
+            .snapshot_mode(SnapshotMode::First)
+            .build_with(MockHttp { responses }, MockDns);
+
+        let config = block_on(client.lookup_domain("example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "snapshot");
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = ProviderRegistry::new();
+
+        registry
+            .insert_xml(ISPDB_CONFIG.replace(
+                "<domain>example.com</domain>",
+                "<domain>*.example.com</domain>",
+            ))
+            .unwrap();
+
+        let mut responses = HashMap::new();
+
+        responses.insert(
+            "http://autoconfig.mail.example.com/mail/config-v1.1.xml",
+            PROVIDER_CONFIG,
+        );
+
+        let client = ClientBuilder::new()
+            .registry(registry)
+            .build_with(MockHttp { responses }, MockDns);
+
+        let config = block_on(client.lookup_domain("mail.example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+        assert!(config.diagnostics().is_empty());
+
+        let candidates = block_on(client.discover_all("mail.example.com"));
+
+        assert_eq!(candidates[0].source().mechanism(), &Mechanism::Registry);
+        assert_eq!(candidates[1].source().mechanism(), &Mechanism::Provider);
+        assert!(candidates[1].is_ok());
+
+        // The wildcard does not match the domain itself.
+        let config = block_on(client.lookup_domain("example.com"));
+
+        assert!(config.is_err());
+
+        let mut registry = ProviderRegistry::new();
+
+        registry
+            .insert_xml(ISPDB_CONFIG.replace(
+                "<domain>example.com</domain>",
+                "<domain>bücher.de</domain><domain>corp</domain>",
+            ))
+            .unwrap();
+
+        let client = ClientBuilder::new().registry(registry).build_with(
+            MockHttp {
+                responses: HashMap::new(),
+            },
+            MockDns,
+        );
+
+        for domain in ["Bücher.de", "xn--bcher-kva.de", "CORP"] {
+            let config = block_on(client.lookup_domain(domain)).unwrap();
+
+            assert_eq!(config.email_provider().id(), "ispdb");
+        }
+
+        assert!(matches!(
+            block_on(client.lookup_domain("intranet"))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::BadInput
+        ));
+    }
+
+    #[test]
+    fn test_internationalized_address() {
+        let mut responses = HashMap::new();
+
+        responses.insert(
+            "https://autoconfig.thunderbird.net/v1.1/xn--bcher-kva.de",
+            ISPDB_CONFIG,
+        );
+
+        let client = Client::with_transports(MockHttp { responses }, MockDns);
+
+        for address in ["Müller@Bücher.DE", "\"m müller\"@xn--bcher-kva.de"] {
+            let config = block_on(client.lookup_addr(address)).unwrap();
+
+            assert_eq!(config.email_provider().id(), "ispdb");
+        }
+
+        assert!(block_on(client.lookup_domain("bücher.de")).is_ok());
+
+        let error = block_on(client.lookup_addr("müller@bücher")).unwrap_err();
+
+        assert!(matches!(error.kind(), ErrorKind::BadInput));
+    }
+
+    #[test]
+    fn test_send_email_address() {
+        let responses = || {
+            let mut responses = HashMap::new();
+
+            responses.insert(
+                "https://autoconfig.example.com/mail/config-v1.1.xml?emailaddress=user%40example.com",
+                PROVIDER_CONFIG,
+            );
+            responses.insert(
+                "https://autoconfig.thunderbird.net/v1.1/example.com",
+                ISPDB_CONFIG,
+            );
+
+            responses
+        };
+
+        let client = ClientBuilder::new().send_email_address(true).build_with(
+            MockHttp {
+                responses: responses(),
+            },
+            MockDns,
+        );
+
+        let config = block_on(client.lookup_addr("user@example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "provider");
+
+        // Lookups by domain have no address of a user to send.
+        let config = block_on(client.lookup_domain("example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+
+        let client = Client::with_transports(
+            MockHttp {
+                responses: responses(),
+            },
+            MockDns,
+        );
+
+        let config = block_on(client.lookup_addr("user@example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+    }
+
+    #[test]
+    fn test_autodiscover_address() {
+        #[derive(Default)]
+        struct RecordingHttp {
+            requests: Mutex<Vec<Request>>,
+        }
+
+        #[async_trait]
+        impl HttpTransport for RecordingHttp {
+            async fn send(&self, request: Request) -> Result<Response> {
+                self.requests.lock().unwrap().push(request);
+
+                Ok(Response::new(404, "Not found"))
+            }
+        }
+
+        struct Office365Dns;
+
+        #[async_trait]
+        impl DnsResolver for Office365Dns {
+            async fn txt(&self, _name: &str) -> Result<Vec<TxtRecord>> {
+                Err(no_records())
+            }
+
+            async fn srv(&self, _name: &str) -> Result<Vec<SrvRecord>> {
+                Err(no_records())
+            }
+
+            async fn mx(&self, _name: &str) -> Result<Vec<MxRecord>> {
+                Ok(vec![MxRecord::new(
+                    0,
+                    "example-com.mail.protection.outlook.com.",
+                )])
+            }
+        }
+
+        let requested = |send_email_address| {
+            let client = ClientBuilder::new()
+                .send_email_address(send_email_address)
+                .disable(Mechanism::Guess)
+                .build_with(RecordingHttp::default(), Office365Dns);
+
+            let _ = block_on(client.lookup_addr("user@example.com"));
+
+            let requests = client.http.requests.lock().unwrap().clone();
+
+            requests
+        };
+
+        let requests = requested(false);
+
+        let v2_urls: Vec<&str> = requests
+            .iter()
+            .map(|request| request.url())
+            .filter(|url| url.starts_with("https://autodiscover-s.outlook.com/"))
+            .collect();
+
+        assert!(!v2_urls.is_empty());
+        assert!(v2_urls
+            .iter()
+            .all(|url| url.contains("/v1.0/postmaster@example.com?")));
+
+        let bodies: Vec<&str> = requests
+            .iter()
+            .filter_map(|request| request.body())
+            .collect();
+
+        assert!(!bodies.is_empty());
+        assert!(bodies
+            .iter()
+            .all(|body| body.contains("postmaster@example.com") && !body.contains("user@")));
+
+        let requests = requested(true);
+
+        assert!(requests
+            .iter()
+            .filter(|request| request
+                .url()
+                .starts_with("https://autodiscover-s.outlook.com/"))
+            .all(|request| request.url().contains("/v1.0/user@example.com?")));
+        assert!(requests
+            .iter()
+            .filter_map(|request| request.body())
+            .all(|body| body.contains("user@example.com")));
+
+        // The address is sent to the provider over https, but not over plain http.
+        assert!(requests.iter().any(|request| request
+            .url()
+            .starts_with("https://autoconfig.example.com/")
+            && request.url().contains("emailaddress=")));
+        assert!(requests
+            .iter()
+            .filter(|request| request.url().starts_with("http://"))
+            .all(|request| !request.url().contains("emailaddress")));
+        assert!(requests
+            .iter()
+            .any(|request| request.url() == "http://autoconfig.example.com/mail/config-v1.1.xml"));
+    }
+
+    #[test]
+    fn test_error_origins() {
+        let client = ClientBuilder::new()
+            .disable(Mechanism::Autodiscover)
+            .disable(Mechanism::AutodiscoverV2)
+            .disable(Mechanism::Guess)
+            .build_with(
+                MockHttp {
+                    responses: HashMap::new(),
+                },
+                MockDns,
+            );
+
+        let error = block_on(client.lookup_domain("example.com")).unwrap_err();
+
+        let errors = match error.kind() {
+            ErrorKind::NotFound(errors) => errors,
+            kind => panic!("Expected NotFound, got {:?}", kind),
+        };
+
+        assert!(errors.iter().all(|error| error.origin().is_some()));
+
+        let ispdb = errors
+            .iter()
+            .find(|error| error.origin().unwrap().mechanism() == &Mechanism::Ispdb)
+            .unwrap();
+
+        assert_eq!(ispdb.status(), Some(404));
+        assert_eq!(
+            ispdb.origin().unwrap().location(),
+            "https://autoconfig.thunderbird.net/v1.1/example.com"
+        );
+
+        let report = error.report();
+
+        // Sources that were tried over both https and plain http list both attempts below them.
+        let attempts: usize = errors
+            .iter()
+            .map(|error| match error.kind() {
+                ErrorKind::NotFound(attempts) => attempts.len(),
+                _ => 0,
+            })
+            .sum();
+
+        assert!(attempts > 0);
+        assert_eq!(report.lines().count(), errors.len() + attempts + 1);
+        assert!(report.contains(
+            "- ISPDB (https://autoconfig.thunderbird.net/v1.1/example.com) failed after"
+        ));
+        assert!(report.contains("with status 404"));
+
+        assert!(error.is_permanent());
+    }
+
+    #[test]
+    fn test_cache() {
+        struct CountingHttp {
+            requests: AtomicUsize,
+        }
+
+        #[async_trait]
+        impl HttpTransport for CountingHttp {
+            async fn send(&self, request: Request) -> Result<Response> {
+                self.requests.fetch_add(1, SeqCst);
+
+                match request.url() {
+                    "https://autoconfig.thunderbird.net/v1.1/example.com" => {
+                        Ok(Response::new(200, ISPDB_CONFIG)
+                            .with_header("Cache-Control", "max-age=60"))
+                    }
+                    _ => Ok(Response::new(404, "Not found")),
+                }
+            }
+        }
+
+        let client = ClientBuilder::new()
+            .disable(Mechanism::Guess)
+            .cache(Cache::memory())
+            .build_with(
+                CountingHttp {
+                    requests: AtomicUsize::new(0),
+                },
+                MockDns,
+            );
+
+        let config = block_on(client.lookup_domain("example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+        assert_eq!(config.max_age(), Some(Duration::from_secs(60)));
+
+        let requests = client.http.requests.load(SeqCst);
+
+        assert!(requests > 0);
+
+        // Both the config and the sources that did not have one are cached.
+        let config = block_on(client.lookup_domain("example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+        assert_eq!(config.trust(), Some(&Trust::Secure));
+        assert_eq!(client.http.requests.load(SeqCst), requests);
+    }
+
+    #[test]
+    fn test_cache_dns() {
+        struct CountingDns {
+            txt: AtomicUsize,
+            mx: AtomicUsize,
+        }
+
+        #[async_trait]
+        impl DnsResolver for CountingDns {
+            async fn txt(&self, _name: &str) -> Result<Vec<TxtRecord>> {
+                self.txt.fetch_add(1, SeqCst);
+
+                Err(no_records())
+            }
+
+            async fn srv(&self, _name: &str) -> Result<Vec<SrvRecord>> {
+                Err(no_records())
+            }
+
+            async fn mx(&self, _name: &str) -> Result<Vec<MxRecord>> {
+                self.mx.fetch_add(1, SeqCst);
+
+                Ok(vec![
+                    MxRecord::new(10, "mx1.mailhost.net.").with_ttl(Duration::from_secs(300))
+                ])
+            }
+        }
+
+        let client = |cache: Option<Cache>| {
+            let mut responses = HashMap::new();
+
+            responses.insert(
+                "https://autoconfig.thunderbird.net/v1.1/mailhost.net",
+                ISPDB_CONFIG,
+            );
+
+            let builder = ClientBuilder::new().disable(Mechanism::Guess);
+
+            let builder = match cache {
+                Some(cache) => builder.cache(cache),
+                None => builder,
+            };
+
+            builder.build_with(
+                MockHttp { responses },
+                CountingDns {
+                    txt: AtomicUsize::new(0),
+                    mx: AtomicUsize::new(0),
+                },
+            )
+        };
+
+        // The MX source and the validation share a single query.
+        let client_without_cache = client(None);
+
+        let config = block_on(client_without_cache.lookup_domain("example.com")).unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+        assert_eq!(client_without_cache.dns.mx.load(SeqCst), 1);
+        assert_eq!(client_without_cache.dns.txt.load(SeqCst), 1);
+
+        let client = client(Some(Cache::memory()));
+
+        block_on(client.lookup_domain("example.com")).unwrap();
+        block_on(client.lookup_domain("example.com")).unwrap();
+
+        assert_eq!(client.dns.mx.load(SeqCst), 1);
+        assert_eq!(client.dns.txt.load(SeqCst), 1);
+    }
+
+    #[test]
+    fn test_cache_autodiscover_per_address() {
+        struct AutodiscoverHttp;
+
+        #[async_trait]
+        impl HttpTransport for AutodiscoverHttp {
+            async fn send(&self, request: Request) -> Result<Response> {
+                if request.url() != "https://autodiscover.example.com/autodiscover/autodiscover.xml"
+                {
+                    return Ok(Response::new(404, "Not found"));
+                }
+
+                let user = match request.body() {
+                    Some(body) if body.contains("alice@example.com") => "alice",
+                    _ => "bob",
+                };
+
+                let body = format!(
+                    r#"<?xml version="1.0" encoding="utf-8"?>
+                    <Autodiscover xmlns="http://schemas.microsoft.com/exchange/autodiscover/responseschema/2006">
+                        <Response xmlns="http://schemas.microsoft.com/exchange/autodiscover/outlook/responseschema/2006a">
+                            <Account>
+                                <AccountType>email</AccountType>
+                                <Action>settings</Action>
+                                <Protocol>
+                                    <Type>IMAP</Type>
+                                    <Server>imap.example.com</Server>
+                                    <Port>993</Port>
+                                    <LoginName>corp\{}</LoginName>
+                                    <SSL>on</SSL>
+                                </Protocol>
+                            </Account>
+                        </Response>
+                    </Autodiscover>"#,
+                    user
+                );
+
+                Ok(Response::new(200, body).with_header("Cache-Control", "max-age=60"))
+            }
+        }
+
+        let client = ClientBuilder::new()
+            .send_email_address(true)
+            .disable(Mechanism::Guess)
+            .cache(Cache::memory())
+            .build_with(AutodiscoverHttp, MockDns);
+
+        let username = |address| {
+            let config = block_on(client.lookup_addr(address)).unwrap();
+
+            let username = config.email_provider().incoming_servers()[0]
+                .username()
+                .map(str::to_string);
+
+            username
+        };
+
+        assert_eq!(
+            username("alice@example.com").as_deref(),
+            Some(r"corp\alice")
+        );
+        assert_eq!(username("bob@example.com").as_deref(), Some(r"corp\bob"));
+        assert_eq!(
+            username("alice@example.com").as_deref(),
+            Some(r"corp\alice")
+        );
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        struct FlakyHttp {
+            failures: AtomicUsize,
+        }
+
+        #[async_trait]
+        impl HttpTransport for FlakyHttp {
+            async fn send(&self, request: Request) -> Result<Response> {
+                if request.url() != "https://autoconfig.thunderbird.net/v1.1/example.com" {
+                    return Ok(Response::new(404, "Not found"));
+                }
+
+                match self
+                    .failures
+                    .fetch_update(SeqCst, SeqCst, |n| n.checked_sub(1))
+                {
+                    Ok(_) => Ok(Response::new(503, "Service unavailable")),
+                    Err(_) => Ok(Response::new(200, ISPDB_CONFIG)),
+                }
+            }
+        }
+
+        let client = |retry_policy: RetryPolicy| {
+            ClientBuilder::new()
+                .disable(Mechanism::Autodiscover)
+                .disable(Mechanism::AutodiscoverV2)
+                .disable(Mechanism::Guess)
+                .retry_policy(retry_policy)
+                .build_with(
+                    FlakyHttp {
+                        failures: AtomicUsize::new(2),
+                    },
+                    MockDns,
+                )
+        };
+
+        let error =
+            block_on(client(RetryPolicy::never()).lookup_domain("example.com")).unwrap_err();
+
+        assert!(error.is_transient());
+
+        let config = block_on(
+            client(RetryPolicy::with_backoff(2, Duration::ZERO)).lookup_domain("example.com"),
+        )
+        .unwrap();
+
+        assert_eq!(config.email_provider().id(), "ispdb");
+
+        assert!(block_on(
+            client(RetryPolicy::with_backoff(1, Duration::ZERO)).lookup_domain("example.com")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_security_policy() {
+        const FOREIGN_CONFIG: &str = r#"
+            <clientConfig version="1.1">
+                <emailProvider id="foreign">
+                    <domain>example.com</domain>
+                    <incomingServer type="imap">
+                        <hostname>imap.example.org</hostname>
+                    </incomingServer>
+                </emailProvider>
+            </clientConfig>
+        "#;
+
+        let client = |policy: SecurityPolicy| {
+            let mut responses = HashMap::new();
+
+            responses.insert(
+                "http://autoconfig.example.com/mail/config-v1.1.xml",
+                FOREIGN_CONFIG,
+            );
+            responses.insert(
+                "https://autoconfig.example.net/mail/config-v1.1.xml",
+                PROVIDER_CONFIG,
+            );
+
+            ClientBuilder::new()
+                .security_policy(policy)
+                .build_with(MockHttp { responses }, MockDns)
+        };
+
+        let url = "https://autoconfig.example.com/mail/config-v1.1.xml";
+        let exchanges = Exchanges::default();
+
+        let config = block_on(client(SecurityPolicy::HttpsFirst).get_provider_config(
+            "example.com",
+            url,
+            &exchanges,
+        ))
+        .unwrap();
+
+        assert_eq!(config.trust(), Some(&Trust::Insecure));
+
+        assert!(
+            block_on(client(SecurityPolicy::HttpsOnly).get_provider_config(
+                "example.com",
+                url,
+                &exchanges
+            ))
+            .is_err()
+        );
+
+        assert!(block_on(
+            client(SecurityPolicy::MatchingHostnames).get_provider_config(
+                "example.com",
+                url,
+                &exchanges
+            )
+        )
+        .is_err());
+
+        let config = block_on(client(SecurityPolicy::HttpsOnly).get_provider_config(
+            "example.net",
+            "https://autoconfig.example.net/mail/config-v1.1.xml",
+            &exchanges,
+        ))
+        .unwrap();
+
+        assert_eq!(config.trust(), Some(&Trust::Secure));
+
+        // Both attempts are reported when neither has a config.
+        let error = block_on(client(SecurityPolicy::HttpsFirst).get_provider_config(
+            "example.org",
+            "https://autoconfig.example.org/mail/config-v1.1.xml",
+            &exchanges,
+        ))
+        .unwrap_err();
+
+        match error.kind() {
+            ErrorKind::NotFound(errors) => {
+                assert_eq!(errors.len(), 2);
+                assert!(errors.iter().all(|error| error.status() == Some(404)));
+            }
+            kind => panic!("Expected NotFound, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_diagnostics_use_mx() {
+        const HOSTED_CONFIG: &str = r#"
+            <clientConfig version="1.1">
+                <emailProvider id="mailhost.net">
+                    <domain>example.com</domain>
+                    <domain>mailhost.net</domain>
+                    <incomingServer type="imap">
+                        <hostname>imap.mailhost.net</hostname>
+                        <port>993</port>
+                        <socketType>SSL</socketType>
+                    </incomingServer>
+                </emailProvider>
+            </clientConfig>
+        "#;
+
+        struct MxDns;
+
+        #[async_trait]
+        impl DnsResolver for MxDns {
+            async fn txt(&self, _name: &str) -> Result<Vec<TxtRecord>> {
+                Err(no_records())
+            }
+
+            async fn srv(&self, _name: &str) -> Result<Vec<SrvRecord>> {
+                Err(no_records())
+            }
+
+            async fn mx(&self, _name: &str) -> Result<Vec<MxRecord>> {
+                Ok(vec![MxRecord::new(10, "mx1.mailhost.net.")])
+            }
+        }
+
+        let responses = || {
+            let mut responses = HashMap::new();
+
+            responses.insert(
+                "https://autoconfig.thunderbird.net/v1.1/example.com",
+                HOSTED_CONFIG,
+            );
+
+            MockHttp { responses }
+        };
+
+        let client = ClientBuilder::new()
+            .disable(Mechanism::Mx)
+            .build_with(responses(), MxDns);
+
+        let config = block_on(client.lookup_domain("example.com")).unwrap();
+
+        assert!(config.diagnostics().is_empty());
+
+        // The domains the config lists do not relate its servers to the domain.
+        let client = ClientBuilder::new().build_with(responses(), MockDns);
+
+        let config = block_on(client.lookup_domain("example.com")).unwrap();
+
+        assert_eq!(
+            config.diagnostics(),
+            &[Diagnostic::UnrelatedHostname(
+                "imap.mailhost.net".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_txt_record_requires_https() {
+        struct TxtDns;
+
+        #[async_trait]
+        impl DnsResolver for TxtDns {
+            async fn txt(&self, _name: &str) -> Result<Vec<TxtRecord>> {
+                Ok(vec![TxtRecord::new(Bytes::from_static(
+                    b"mailconf=https://config.example.net/config-v1.1.xml",
+                ))])
+            }
+
+            async fn srv(&self, _name: &str) -> Result<Vec<SrvRecord>> {
+                Err(no_records())
+            }
+
+            async fn mx(&self, _name: &str) -> Result<Vec<MxRecord>> {
+                Err(no_records())
+            }
+        }
+
+        let mut responses = HashMap::new();
+
+        responses.insert("http://config.example.net/config-v1.1.xml", PROVIDER_CONFIG);
+
+        let client = ClientBuilder::new()
+            .disable(Mechanism::Guess)
+            .build_with(MockHttp { responses }, TxtDns);
+
+        let error = block_on(client.lookup_domain("example.com")).unwrap_err();
+
+        let errors = match error.kind() {
+            ErrorKind::NotFound(errors) => errors,
+            kind => panic!("Expected NotFound, got {:?}", kind),
+        };
+
+        let txt = errors
+            .iter()
+            .find(|error| error.origin().unwrap().mechanism() == &Mechanism::TxtRecord)
+            .unwrap();
+
+        assert_eq!(
+            txt.origin().unwrap().location(),
+            "https://config.example.net/config-v1.1.xml"
+        );
+        assert_eq!(txt.status(), Some(404));
+    }
+
+    #[test]
+    fn test_autodiscover_v2() {
+        let mut responses = HashMap::new();
+
+        responses.insert(
+            "https://autodiscover-s.outlook.com/autodiscover/autodiscover.json/v1.0/user@example.com?Protocol=EWS",
+            r#"{"Protocol":"EWS","Url":"https://outlook.office365.com/EWS/Exchange.asmx"}"#,
+        );
+        responses.insert(
+            "https://autodiscover-s.outlook.com/autodiscover/autodiscover.json/v1.0/user@example.com?Protocol=IMAP",
+            r#"{"Protocol":"IMAP","Url":"outlook.office365.com"}"#,
+        );
+
+        let client = Client::with_transports(MockHttp { responses }, MockDns);
+
+        let exchanges = Exchanges::new(Ok((
+            vec![String::from("example-com.mail.protection.outlook.com")],
+            None,
+        )));
+
+        let config = block_on(client.get_autodiscover_v2_config(
+            "example.com",
+            "user@example.com",
+            &exchanges,
+        ))
+        .unwrap();
+
+        let incoming = config.email_provider().incoming_servers();
+
+        assert_eq!(incoming.len(), 2);
+        assert_eq!(incoming[0].server_type(), &ServerType::Exchange);
+        assert_eq!(incoming[1].server_type(), &ServerType::Imap);
+
+        assert!(block_on(client.get_autodiscover_v2_config(
+            "example.org",
+            "user@example.org",
+            &exchanges
+        ))
+        .is_err());
+
+        // Domains whose mail is not handled by Office 365 are not looked up at all.
+        let error = block_on(client.get_autodiscover_v2_config(
+            "example.com",
+            "user@example.com",
+            &Exchanges::default(),
+        ))
+        .unwrap_err();
+
+        assert!(error.is_permanent());
     }
 }
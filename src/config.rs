@@ -1,8 +1,17 @@
 use std::time::Duration;
 
-use serde::Deserialize;
-
-#[derive(Debug, Deserialize, PartialEq)]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    discovery::Trust,
+    error::{Error, ErrorKind, Result},
+    parse,
+    placeholder::Placeholders,
+    validate::{self, Diagnostic},
+    write,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 /// A config that follows Mozilla's specification: https://wiki.mozilla.org/Thunderbird:Autoconfiguration:ConfigFileFormat
 pub struct Config {
@@ -10,9 +19,44 @@ pub struct Config {
     email_provider: EmailProvider,
     #[serde(rename = "oAuth2")]
     oauth2: Option<OAuth2Config>,
+    #[serde(default)]
+    guessed: bool,
+    #[serde(default)]
+    trust: Option<Trust>,
+    #[serde(default)]
+    diagnostics: Vec<Diagnostic>,
+    #[serde(skip)]
+    max_age: Option<Duration>,
 }
 
 impl Config {
+    pub(crate) fn new(email_provider: EmailProvider) -> Self {
+        Self {
+            version: String::from("1.1"),
+            email_provider,
+            oauth2: None,
+            guessed: false,
+            trust: None,
+            diagnostics: Vec::new(),
+            max_age: None,
+        }
+    }
+
+    /// Creates a builder to construct a config by hand, e.g. for a manual-entry fallback or a provider override.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Parses a config from a `config-v1.1.xml` file.
+    pub fn from_xml<B: AsRef<[u8]>>(bytes: B) -> Result<Config> {
+        parse::from_bytes(bytes)
+    }
+
+    /// Writes the config as a `config-v1.1.xml` file, which can be read again using [`Config::from_xml`].
+    pub fn to_xml(&self) -> String {
+        write::to_string(self)
+    }
+
     /// The config version
     pub fn version(&self) -> &str {
         &self.version
@@ -27,9 +71,244 @@ impl Config {
     pub fn oauth2(&self) -> Option<&OAuth2Config> {
         self.oauth2.as_ref()
     }
+
+    /// Whether the config was guessed by probing common hostnames and ports, instead of being published by the provider or the ISPDB.
+    ///
+    /// A guessed config should be shown to the user for confirmation before it is used.
+    pub fn is_guessed(&self) -> bool {
+        self.guessed
+    }
+
+    /// How much the config can be trusted based on how it was retrieved, only known for configs returned by a discovery.
+    pub fn trust(&self) -> Option<&Trust> {
+        self.trust.as_ref()
+    }
+
+    /// Checks whether the config is consistent with the domain it is used for, see [`Diagnostic`] for what is checked.
+    pub fn validate<D: AsRef<str>>(&self, domain: D) -> Vec<Diagnostic> {
+        validate::validate(self, domain)
+    }
+
+    /// Checks whether the config is consistent with the domain it is used for, accepting servers hosted by the domain's mail exchangers, see [`validate::validate_with_mx`].
+    pub fn validate_with_mx<D: AsRef<str>, M: AsRef<str>>(
+        &self,
+        domain: D,
+        exchanges: &[M],
+    ) -> Vec<Diagnostic> {
+        validate::validate_with_mx(self, domain, exchanges)
+    }
+
+    /// The warnings that were found when the config was validated against the requested domain, only filled in for configs returned by a discovery.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// How long the config may be cached according to the source it was retrieved from, e.g. the `Cache-Control` header or the TTL of the DNS records it is based on.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Lowers how long the config may be cached, a config is never cached longer than any of the responses it is based on.
+    pub(crate) fn limit_max_age(&mut self, max_age: Option<Duration>) {
+        self.max_age = match (self.max_age, max_age) {
+            (Some(current), Some(max_age)) => Some(current.min(max_age)),
+            (current, max_age) => current.or(max_age),
+        };
+    }
+
+    pub(crate) fn set_diagnostics(&mut self, diagnostics: Vec<Diagnostic>) {
+        self.diagnostics = diagnostics;
+    }
+
+    pub(crate) fn set_trust(&mut self, trust: Trust) {
+        self.trust = Some(trust);
+    }
+
+    #[cfg(any(feature = "verify", test))]
+    pub(crate) fn set_guessed(&mut self, guessed: bool) {
+        self.guessed = guessed;
+    }
+
+    /// Forgets how the config was retrieved, a config file must not be able to claim it can be trusted.
+    pub(crate) fn clear_metadata(&mut self) {
+        self.guessed = false;
+        self.trust = None;
+        self.diagnostics.clear();
+    }
+
+    /// Returns a copy of the config where the placeholders (`%EMAILADDRESS%`, `%EMAILLOCALPART%`, `%EMAILDOMAIN%` and `%REALNAME%`) in the servers usernames, hostnames and urls are replaced with the users details.
+    ///
+    /// The details are percent encoded where they are inserted into a url, including the oAuth2 urls.
+    ///
+    /// Fails if the email address is invalid or the config contains a placeholder that is not known.
+    pub fn resolve<E: AsRef<str>>(
+        &self,
+        email_address: E,
+        real_name: Option<&str>,
+    ) -> Result<Config> {
+        let placeholders = Placeholders::new(email_address.as_ref(), real_name)?;
+
+        let mut config = self.clone();
+
+        for property in config.email_provider.properties.iter_mut() {
+            match property {
+                EmailProviderProperty::IncomingServer(server)
+                | EmailProviderProperty::OutgoingServer(server) => server.resolve(&placeholders)?,
+                _ => {}
+            }
+        }
+
+        if let Some(oauth2) = config.oauth2.as_mut() {
+            oauth2.auth_url = placeholders.substitute_url(&oauth2.auth_url)?;
+            oauth2.token_url = placeholders.substitute_url(&oauth2.token_url)?;
+        }
+
+        Ok(config)
+    }
+}
+
+/// Used to construct a [`Config`], see [`Config::builder`].
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    id: Option<String>,
+    properties: Vec<EmailProviderProperty>,
+    oauth2: Option<OAuth2Config>,
+}
+
+impl ConfigBuilder {
+    /// The email providers unique id, this is required.
+    pub fn provider<I: Into<String>>(mut self, id: I) -> Self {
+        self.id = Some(id.into());
+
+        self
+    }
+
+    /// A domain name that the email provider uses in their email addresses, at least one is required.
+    pub fn domain<D: Into<String>>(mut self, domain: D) -> Self {
+        self.properties
+            .push(EmailProviderProperty::Domain(domain.into()));
+
+        self
+    }
+
+    /// The email providers display name. e.g. Google Mail
+    pub fn display_name<N: Into<String>>(mut self, name: N) -> Self {
+        self.properties
+            .push(EmailProviderProperty::DisplayName(name.into()));
+
+        self
+    }
+
+    /// The email providers short display name. e.g. GMail
+    pub fn display_short_name<N: Into<String>>(mut self, name: N) -> Self {
+        self.properties
+            .push(EmailProviderProperty::DisplayShortName(name.into()));
+
+        self
+    }
+
+    /// Adds an incoming mail server, at least one is required.
+    pub fn incoming(mut self, server: Server) -> Self {
+        self.properties
+            .push(EmailProviderProperty::IncomingServer(server));
+
+        self
+    }
+
+    /// Adds an outgoing mail server, which has to be an SMTP server.
+    pub fn outgoing(mut self, server: Server) -> Self {
+        self.properties
+            .push(EmailProviderProperty::OutgoingServer(server));
+
+        self
+    }
+
+    /// Adds a link to documentation on how to setup the email client.
+    pub fn documentation<U: Into<String>, D: Into<String>>(
+        mut self,
+        url: U,
+        description: D,
+    ) -> Self {
+        self.properties
+            .push(EmailProviderProperty::Documentation(Documentation {
+                url: url.into(),
+                properties: vec![DocumentationDescription {
+                    lang: None,
+                    description: description.into(),
+                }],
+            }));
+
+        self
+    }
+
+    /// The oAuth2 details of the email provider, the scopes are separated by spaces.
+    pub fn oauth2<I: Into<String>, S: Into<String>, A: Into<String>, T: Into<String>>(
+        mut self,
+        issuer: I,
+        scope: S,
+        auth_url: A,
+        token_url: T,
+    ) -> Self {
+        self.oauth2 = Some(OAuth2Config {
+            issuer: issuer.into(),
+            scope: scope.into(),
+            auth_url: auth_url.into(),
+            token_url: token_url.into(),
+        });
+
+        self
+    }
+
+    /// Validates the required fields and creates the config.
+    pub fn build(self) -> Result<Config> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidConfig, message);
+
+        let id = match self.id {
+            Some(id) if !id.trim().is_empty() => id,
+            _ => return Err(invalid("A config requires a provider id")),
+        };
+
+        let mut has_domain = false;
+        let mut has_incoming = false;
+
+        for property in &self.properties {
+            match property {
+                EmailProviderProperty::Domain(_) => has_domain = true,
+                EmailProviderProperty::IncomingServer(server) => {
+                    if server.server_type() == &ServerType::Smtp {
+                        return Err(invalid(
+                            "An SMTP server can not be used as an incoming server",
+                        ));
+                    }
+
+                    has_incoming = true
+                }
+                EmailProviderProperty::OutgoingServer(server)
+                    if server.server_type() != &ServerType::Smtp =>
+                {
+                    return Err(invalid("An outgoing server has to be an SMTP server"));
+                }
+                _ => {}
+            }
+        }
+
+        if !has_domain {
+            return Err(invalid("A config requires at least one domain"));
+        }
+
+        if !has_incoming {
+            return Err(invalid("A config requires at least one incoming server"));
+        }
+
+        let mut config = Config::new(EmailProvider::new(id, self.properties));
+
+        config.oauth2 = self.oauth2;
+
+        Ok(config)
+    }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct OAuth2Config {
     issuer: String,
@@ -62,14 +341,21 @@ impl OAuth2Config {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct EmailProvider {
     id: String,
-    #[serde(rename = "$value")]
+    #[serde(rename(deserialize = "$value"), alias = "properties")]
     properties: Vec<EmailProviderProperty>,
 }
 
 impl EmailProvider {
+    pub(crate) fn new<S: Into<String>>(id: S, properties: Vec<EmailProviderProperty>) -> Self {
+        Self {
+            id: id.into(),
+            properties,
+        }
+    }
+
     /// Just an array containing all of the email providers properties, usefull if you want to get multiple properties in 1 for loop.
     pub fn properties(&self) -> &Vec<EmailProviderProperty> {
         &self.properties
@@ -174,7 +460,7 @@ impl EmailProvider {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum EmailProviderProperty {
     Domain(String),
@@ -185,19 +471,95 @@ pub enum EmailProviderProperty {
     Documentation(Documentation),
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Server {
     r#type: ServerType,
-    #[serde(rename = "$value")]
+    #[serde(rename(deserialize = "$value"), alias = "properties")]
     properties: Vec<ServerProperty>,
 }
 
 impl Server {
+    pub(crate) fn new(r#type: ServerType, properties: Vec<ServerProperty>) -> Self {
+        Self { r#type, properties }
+    }
+
+    fn with_connection<H: Into<String>>(
+        r#type: ServerType,
+        hostname: H,
+        port: u16,
+        security_type: SecurityType,
+    ) -> Self {
+        Self::new(
+            r#type,
+            vec![
+                ServerProperty::Hostname(hostname.into()),
+                ServerProperty::Port(port),
+                ServerProperty::SocketType(security_type),
+            ],
+        )
+    }
+
+    /// Creates an IMAP server, to be used with [`ConfigBuilder::incoming`].
+    pub fn imap<H: Into<String>>(hostname: H, port: u16, security_type: SecurityType) -> Self {
+        Self::with_connection(ServerType::Imap, hostname, port, security_type)
+    }
+
+    /// Creates a POP3 server, to be used with [`ConfigBuilder::incoming`].
+    pub fn pop3<H: Into<String>>(hostname: H, port: u16, security_type: SecurityType) -> Self {
+        Self::with_connection(ServerType::Pop3, hostname, port, security_type)
+    }
+
+    /// Creates an SMTP server, to be used with [`ConfigBuilder::outgoing`].
+    pub fn smtp<H: Into<String>>(hostname: H, port: u16, security_type: SecurityType) -> Self {
+        Self::with_connection(ServerType::Smtp, hostname, port, security_type)
+    }
+
+    /// Creates an Exchange server, to be used with [`ConfigBuilder::incoming`].
+    pub fn exchange<H: Into<String>>(hostname: H) -> Self {
+        Self::new(
+            ServerType::Exchange,
+            vec![ServerProperty::Hostname(hostname.into())],
+        )
+    }
+
+    /// Sets the username used to login, which may contain placeholders like `%EMAILADDRESS%`.
+    pub fn with_username<U: Into<String>>(self, username: U) -> Self {
+        self.with_property(ServerProperty::Username(username.into()))
+    }
+
+    /// Adds a kind of authentication the server supports.
+    pub fn with_authentication(self, authentication_type: AuthenticationType) -> Self {
+        self.with_property(ServerProperty::Authentication(authentication_type))
+    }
+
+    /// Adds any other property to the server.
+    pub fn with_property(mut self, property: ServerProperty) -> Self {
+        self.properties.push(property);
+
+        self
+    }
+
     /// Just an array containing all of a mail servers properties, usefull if you want to get multiple properties in 1 for loop.
     pub fn properties(&self) -> &Vec<ServerProperty> {
         &self.properties
     }
 
+    fn resolve(&mut self, placeholders: &Placeholders) -> Result<()> {
+        for property in self.properties.iter_mut() {
+            match property {
+                ServerProperty::Hostname(value) | ServerProperty::Username(value) => {
+                    *value = placeholders.substitute(value)?
+                }
+                ServerProperty::OwaURL(value)
+                | ServerProperty::EwsURL(value)
+                | ServerProperty::EasURL(value) => *value = placeholders.substitute_url(value)?,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
     /// What type of mail server this server is.
     pub fn server_type(&self) -> &ServerType {
         &self.r#type
@@ -280,7 +642,7 @@ impl Server {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum ServerProperty {
     Hostname(String),
@@ -290,13 +652,15 @@ pub enum ServerProperty {
     Authentication(AuthenticationType),
     OwaURL(String),
     EwsURL(String),
+    /// The url of an Exchange ActiveSync service, this is not part of Thunderbird's format but is returned by Autodiscover, so it is left out of written XML.
+    EasURL(String),
     UseGlobalPreferredServer(bool),
     Pop3(Pop3Config),
     Username(String),
     Password(String),
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub enum SecurityType {
     #[serde(rename = "plain")]
     Plain,
@@ -306,7 +670,7 @@ pub enum SecurityType {
     Tls,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum ServerType {
     Exchange,
@@ -315,7 +679,7 @@ pub enum ServerType {
     Smtp,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub enum AuthenticationType {
     #[serde(rename = "password-cleartext")]
     PasswordCleartext,
@@ -334,7 +698,7 @@ pub enum AuthenticationType {
     None,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Pop3Config {
     leave_messages_on_server: bool,
@@ -376,15 +740,15 @@ impl Pop3Config {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 struct CheckInterval {
     minutes: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Documentation {
     url: String,
-    #[serde(rename = "$value")]
+    #[serde(rename(deserialize = "$value"), alias = "properties")]
     properties: Vec<DocumentationDescription>,
 }
 
@@ -400,10 +764,10 @@ impl Documentation {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct DocumentationDescription {
     lang: Option<String>,
-    #[serde(rename = "$value")]
+    #[serde(rename(deserialize = "$value"), alias = "description")]
     description: String,
 }
 
@@ -421,3 +785,76 @@ impl DocumentationDescription {
         &self.description
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AuthenticationType, Config, SecurityType, Server};
+
+    #[test]
+    fn test_builder() {
+        let config = Config::builder()
+            .provider("example.com")
+            .domain("example.com")
+            .display_name("Example Mail")
+            .incoming(
+                Server::imap("imap.example.com", 993, SecurityType::Tls)
+                    .with_username("%EMAILADDRESS%")
+                    .with_authentication(AuthenticationType::PasswordCleartext),
+            )
+            .outgoing(Server::smtp(
+                "smtp.example.com",
+                587,
+                SecurityType::Starttls,
+            ))
+            .build()
+            .unwrap();
+
+        let parsed = Config::from_xml(
+            r#"
+            <clientConfig version="1.1">
+                <emailProvider id="example.com">
+                    <domain>example.com</domain>
+                    <displayName>Example Mail</displayName>
+                    <incomingServer type="imap">
+                        <hostname>imap.example.com</hostname>
+                        <port>993</port>
+                        <socketType>SSL</socketType>
+                        <username>%EMAILADDRESS%</username>
+                        <authentication>password-cleartext</authentication>
+                    </incomingServer>
+                    <outgoingServer type="smtp">
+                        <hostname>smtp.example.com</hostname>
+                        <port>587</port>
+                        <socketType>STARTTLS</socketType>
+                    </outgoingServer>
+                </emailProvider>
+            </clientConfig>
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(config, parsed);
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        assert!(Config::builder()
+            .domain("example.com")
+            .incoming(Server::imap("imap.example.com", 993, SecurityType::Tls))
+            .build()
+            .is_err());
+
+        assert!(Config::builder()
+            .provider("example.com")
+            .incoming(Server::imap("imap.example.com", 993, SecurityType::Tls))
+            .build()
+            .is_err());
+
+        assert!(Config::builder()
+            .provider("example.com")
+            .domain("example.com")
+            .incoming(Server::smtp("smtp.example.com", 465, SecurityType::Tls))
+            .build()
+            .is_err());
+    }
+}
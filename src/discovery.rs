@@ -0,0 +1,300 @@
+use std::{cmp::Ordering, fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+};
+
+/// The different mechanisms that can be used to discover a config.
+///
+/// The variants are declared in order of how much we trust the config they produce, which is also the order used to rank candidates:
+/// configs provided by the application come first, then configs hosted by the provider itself, then configs derived from the provider's DNS records, then configs from Mozilla's ISPDB and lastly a guess.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Mechanism {
+    /// A config from the client's registry, see [`crate::registry::ProviderRegistry`].
+    Registry,
+    /// A config hosted by the provider at `autoconfig.<domain>`.
+    Provider,
+    /// A config hosted by the provider at `<domain>/.well-known/autoconfig`.
+    WellKnown,
+    /// A config at a url published in a `mailconf=` TXT record of the domain.
+    TxtRecord,
+    /// Settings returned by a Microsoft Autodiscover server.
+    Autodiscover,
+    /// A config assembled from the RFC 6186 SRV records of the domain.
+    Srv,
+    /// A config from Mozilla's ISPDB.
+    Ispdb,
+    /// Endpoints returned by the Autodiscover v2 JSON service of Office 365.
+    ///
+    /// This is ranked below the ISPDB, as the service is not hosted by the provider. It also answers for domains it does not host,
+    /// so it is only asked about domains whose mail exchangers are run by Office 365 and only servers within Office 365 are accepted.
+    AutodiscoverV2,
+    /// A config from Mozilla's ISPDB for the provider that hosts the domain's mail exchanger.
+    Mx,
+    /// A config from an offline snapshot of the ISPDB, either embedded at build time or loaded at runtime, see [`crate::ispdb::Snapshot`].
+    ///
+    /// This is ranked below the online ISPDB, as the snapshot may be outdated.
+    Snapshot,
+    /// A config guessed by connecting to common mail server hostnames and ports, only available with the `verify` feature.
+    Guess,
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Mechanism::Registry => "registry",
+            Mechanism::Provider => "provider",
+            Mechanism::WellKnown => "well-known",
+            Mechanism::TxtRecord => "TXT record",
+            Mechanism::Autodiscover => "autodiscover",
+            Mechanism::Srv => "SRV records",
+            Mechanism::Ispdb => "ISPDB",
+            Mechanism::AutodiscoverV2 => "autodiscover v2",
+            Mechanism::Mx => "MX record",
+            Mechanism::Snapshot => "offline snapshot",
+            Mechanism::Guess => "guess",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// How to pick a config when multiple sources return one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Return the config of whichever source responds first. This is the fastest, but the returned config can differ between runs depending on network latency.
+    Fastest,
+    /// Return the config of the most trustworthy source according to its [`Mechanism`].
+    ///
+    /// Once a config is found, sources with a higher priority get an additional `grace` period to finish before the best config found so far is returned.
+    Priority { grace: Duration },
+}
+
+impl Strategy {
+    /// The grace period used by the default strategy.
+    pub const DEFAULT_GRACE: Duration = Duration::from_secs(2);
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Self::Priority {
+            grace: Self::DEFAULT_GRACE,
+        }
+    }
+}
+
+/// Whether configs may be fetched over plain http, which allows an on-path attacker to point the user to their own servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SecurityPolicy {
+    /// Try https first and fall back to plain http if that fails.
+    #[default]
+    HttpsFirst,
+    /// Never fetch a config over plain http.
+    HttpsOnly,
+    /// Try https first and only accept a config from plain http if all of its servers are hosted under the requested domain or the domain of its mail exchanger.
+    MatchingHostnames,
+}
+
+/// How a lookup is retried when it fails with a transient error, see [`Error::is_transient`].
+///
+/// The wait before the first retry is the `backoff`, which doubles for every retry after that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    retries: u32,
+    backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// The wait before the first retry used by [`RetryPolicy::new`].
+    pub const DEFAULT_BACKOFF: Duration = Duration::from_millis(500);
+
+    /// Retries a failed lookup at most `retries` times, starting with the default backoff.
+    pub fn new(retries: u32) -> Self {
+        Self::with_backoff(retries, Self::DEFAULT_BACKOFF)
+    }
+
+    /// Retries a failed lookup at most `retries` times, waiting `backoff` before the first retry.
+    pub fn with_backoff(retries: u32, backoff: Duration) -> Self {
+        Self { retries, backoff }
+    }
+
+    /// A policy that never retries, which is the default.
+    pub fn never() -> Self {
+        Self::new(0)
+    }
+
+    /// The maximum amount of retries.
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// How long to wait before the given retry, counting from zero.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2_u32.checked_pow(retry).unwrap_or(u32::MAX))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::never()
+    }
+}
+
+/// How much a discovered config can be trusted, based on how it was retrieved.
+///
+/// The variants are declared from most to least trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Trust {
+    /// Retrieved over https.
+    Secure,
+    /// Retrieved over an insecure channel like plain http or DNS, but all of its servers are hosted under the requested domain or the domain of its mail exchanger.
+    MatchingHostnames,
+    /// Retrieved over an insecure channel, so it could have been tampered with.
+    Insecure,
+}
+
+/// Where a config was (or was attempted to be) retrieved from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Source {
+    mechanism: Mechanism,
+    location: String,
+}
+
+impl Source {
+    pub(crate) fn new<L: Into<String>>(mechanism: Mechanism, location: L) -> Self {
+        Self {
+            mechanism,
+            location: location.into(),
+        }
+    }
+
+    /// The mechanism that was used.
+    pub fn mechanism(&self) -> &Mechanism {
+        &self.mechanism
+    }
+
+    /// The url that was requested, or the DNS name that was queried for DNS based mechanisms.
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.mechanism, self.location)
+    }
+}
+
+/// The outcome of a single discovery source.
+#[derive(Debug)]
+pub struct Candidate {
+    source: Source,
+    elapsed: Duration,
+    result: Result<Config>,
+}
+
+impl Candidate {
+    pub(crate) fn new(source: Source, elapsed: Duration, result: Result<Config>) -> Self {
+        Self {
+            source,
+            elapsed,
+            result,
+        }
+    }
+
+    /// Where this candidate was retrieved from.
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    /// How long it took to get a response from the source.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The config that was found, if the source returned one.
+    pub fn config(&self) -> Option<&Config> {
+        self.result.as_ref().ok()
+    }
+
+    /// The error that occurred, if the source did not return a config.
+    pub fn error(&self) -> Option<&Error> {
+        self.result.as_ref().err()
+    }
+
+    /// Whether the source returned a config.
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// Consumes the candidate, returning the result of the source.
+    pub fn into_result(self) -> Result<Config> {
+        self.result
+    }
+
+    /// Compares two candidates by rank: candidates with a config come before failed ones, after that they are ordered by their [`Mechanism`].
+    pub fn rank(&self, other: &Self) -> Ordering {
+        other
+            .is_ok()
+            .cmp(&self.is_ok())
+            .then_with(|| self.source.mechanism.cmp(&other.source.mechanism))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        config::{Config, EmailProvider},
+        error::{Error, ErrorKind},
+    };
+
+    use super::{Candidate, Mechanism, Source};
+
+    fn candidate(mechanism: Mechanism, ok: bool) -> Candidate {
+        let result = if ok {
+            Ok(Config::new(EmailProvider::new("example.com", Vec::new())))
+        } else {
+            Err(Error::new(ErrorKind::InvalidResponse, "Failed"))
+        };
+
+        Candidate::new(
+            Source::new(mechanism, "example.com"),
+            Duration::ZERO,
+            result,
+        )
+    }
+
+    #[test]
+    fn test_rank() {
+        let mut candidates = [
+            candidate(Mechanism::Provider, false),
+            candidate(Mechanism::Ispdb, true),
+            candidate(Mechanism::Mx, false),
+            candidate(Mechanism::WellKnown, true),
+        ];
+
+        candidates.sort_by(Candidate::rank);
+
+        let ranked: Vec<_> = candidates
+            .iter()
+            .map(|candidate| (*candidate.source().mechanism(), candidate.is_ok()))
+            .collect();
+
+        assert_eq!(
+            ranked,
+            vec![
+                (Mechanism::WellKnown, true),
+                (Mechanism::Ispdb, true),
+                (Mechanism::Provider, false),
+                (Mechanism::Mx, false),
+            ]
+        );
+    }
+}
@@ -1,8 +1,14 @@
-use std::cmp::Ordering;
+use std::time::{Duration, Instant};
 
-use bytes::Bytes;
+use async_trait::async_trait;
 
-use trust_dns_resolver::config::ResolverConfig;
+use trust_dns_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+    name_server::{GenericConnection, GenericConnectionProvider},
+    proto::op::ResponseCode,
+    AsyncResolver,
+};
 
 #[cfg(feature = "runtime-tokio")]
 use trust_dns_resolver::TokioAsyncResolver;
@@ -10,73 +16,255 @@ use trust_dns_resolver::TokioAsyncResolver;
 #[cfg(feature = "runtime-async-std")]
 use async_std_resolver::{resolver, AsyncStdResolver};
 
-use trust_dns_proto::rr::rdata::MX;
+use crate::{
+    error::{Error, ErrorKind, Result},
+    runtime::{Runtime, SpawnerRuntime},
+    transport::{DnsResolver, MxRecord, SrvRecord, TxtRecord},
+};
 
-use crate::error::Result;
+type SpawnerResolver = AsyncResolver<GenericConnection, GenericConnectionProvider<SpawnerRuntime>>;
 
-pub struct Dns {
+enum Resolver {
     #[cfg(feature = "runtime-tokio")]
-    resolver: TokioAsyncResolver,
+    Tokio(TokioAsyncResolver),
     #[cfg(feature = "runtime-async-std")]
-    resolver: AsyncStdResolver,
+    AsyncStd(AsyncStdResolver),
+    Spawner(SpawnerResolver),
 }
 
-pub struct SortableMX {
-    mx: MX,
+/// Runs a lookup on whichever resolver the runtime was selected for, they all return the same lookup types.
+macro_rules! lookup {
+    ($resolver:expr, $method:ident, $name:expr) => {
+        match $resolver {
+            #[cfg(feature = "runtime-tokio")]
+            Resolver::Tokio(resolver) => resolver.$method($name).await,
+            #[cfg(feature = "runtime-async-std")]
+            Resolver::AsyncStd(resolver) => resolver.$method($name).await,
+            Resolver::Spawner(resolver) => resolver.$method($name).await,
+        }
+    };
 }
 
-impl Ord for SortableMX {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.mx.preference().cmp(&other.mx.preference())
-    }
+/// How long the records of a lookup that is valid until the given instant may still be cached.
+fn ttl(valid_until: Instant) -> Duration {
+    valid_until.saturating_duration_since(Instant::now())
 }
 
-impl PartialOrd for SortableMX {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+/// Names without any records of the requested type are reported as [`ErrorKind::NoRecordsFound`], like [`DnsResolver`] requires.
+///
+/// A SERVFAIL response is not, as the DNS server could not answer at all.
+fn lookup_error(error: ResolveError) -> Error {
+    match error.kind() {
+        ResolveErrorKind::NoRecordsFound { response_code, .. }
+            if *response_code != ResponseCode::ServFail =>
+        {
+            Error::new(
+                ErrorKind::NoRecordsFound,
+                "Name does not have any records of the requested type",
+            )
+        }
+        _ => error.into(),
     }
 }
 
-impl Eq for SortableMX {}
-
-impl PartialEq for SortableMX {
-    fn eq(&self, other: &Self) -> bool {
-        self.mx.preference() == other.mx.preference()
-    }
+/// The default [`DnsResolver`], which uses trust-dns on the [`Runtime`] it was created for.
+pub struct Dns {
+    resolver: Resolver,
 }
 
 impl Dns {
     pub async fn new() -> Result<Self> {
-        #[cfg(feature = "runtime-tokio")]
-        let resolver = TokioAsyncResolver::tokio(ResolverConfig::default(), Default::default())?;
+        Self::with_config(ResolverConfig::default(), ResolverOpts::default()).await
+    }
 
-        #[cfg(feature = "runtime-async-std")]
-        let resolver = resolver(ResolverConfig::default(), Default::default()).await?;
+    /// Creates a resolver that uses the given configuration instead of the default one, on the default [`Runtime`].
+    pub async fn with_config(config: ResolverConfig, opts: ResolverOpts) -> Result<Self> {
+        Self::with_runtime(config, opts, Runtime::default()).await
+    }
 
-        let dns = Self { resolver };
+    /// Creates a resolver that uses the given configuration and runs on the given runtime.
+    pub async fn with_runtime(
+        config: ResolverConfig,
+        opts: ResolverOpts,
+        runtime: Runtime,
+    ) -> Result<Self> {
+        let resolver = match runtime {
+            #[cfg(feature = "runtime-tokio")]
+            Runtime::Tokio => Resolver::Tokio(TokioAsyncResolver::tokio(config, opts)?),
+            #[cfg(feature = "runtime-async-std")]
+            Runtime::AsyncStd => Resolver::AsyncStd(resolver(config, opts).await?),
+            Runtime::Spawner(spawner) => {
+                Resolver::Spawner(SpawnerResolver::new(config, opts, spawner)?)
+            }
+        };
 
-        Ok(dns)
+        Ok(Self { resolver })
     }
+}
 
-    pub async fn get_txt<N: AsRef<str>>(&self, name: N) -> Result<Vec<Bytes>> {
-        let lookup_results = self.resolver.txt_lookup(name.as_ref()).await?;
+#[async_trait]
+impl DnsResolver for Dns {
+    async fn txt(&self, name: &str) -> Result<Vec<TxtRecord>> {
+        let lookup_results = lookup!(&self.resolver, txt_lookup, name).map_err(lookup_error)?;
 
-        let mut records: Vec<_> = Vec::new();
+        let ttl = ttl(lookup_results.as_lookup().valid_until());
 
-        for txt in lookup_results {
-            let mut bytes: Vec<Bytes> = txt
-                .txt_data()
-                .iter()
-                .map(|data| data.to_vec().into())
-                .collect();
+        let records = lookup_results
+            .iter()
+            .filter_map(|txt| txt.txt_data().first())
+            .map(|data| TxtRecord::new(data.to_vec()).with_ttl(ttl))
+            .collect();
 
-            if bytes.first().is_some() {
-                let record = bytes.remove(0);
+        Ok(records)
+    }
 
-                records.push(record);
-            }
-        }
+    async fn srv(&self, name: &str) -> Result<Vec<SrvRecord>> {
+        let lookup_results = lookup!(&self.resolver, srv_lookup, name).map_err(lookup_error)?;
+
+        let ttl = ttl(lookup_results.as_lookup().valid_until());
+
+        let records = lookup_results
+            .into_iter()
+            .map(|srv| {
+                SrvRecord::new(
+                    srv.priority(),
+                    srv.weight(),
+                    srv.port(),
+                    srv.target().to_utf8(),
+                )
+                .with_ttl(ttl)
+            })
+            .collect();
+
+        Ok(records)
+    }
+
+    async fn mx(&self, name: &str) -> Result<Vec<MxRecord>> {
+        let lookup_results = lookup!(&self.resolver, mx_lookup, name).map_err(lookup_error)?;
+
+        let ttl = ttl(lookup_results.as_lookup().valid_until());
+
+        let records = lookup_results
+            .into_iter()
+            .map(|mx| MxRecord::new(mx.preference(), mx.exchange().to_utf8()).with_ttl(ttl))
+            .collect();
 
         Ok(records)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, UdpSocket},
+        thread,
+        time::Duration,
+    };
+
+    use futures::executor::block_on;
+    use trust_dns_resolver::{
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        proto::{
+            op::{Message, MessageType, ResponseCode},
+            rr::{rdata::MX, Name, RData, Record},
+        },
+    };
+
+    use crate::{
+        error::ErrorKind,
+        runtime::{Runtime, Spawner},
+        transport::DnsResolver,
+    };
+
+    use super::Dns;
+
+    /// Answers a single query with the given response code and answer.
+    fn serve(socket: UdpSocket, response_code: ResponseCode, answer: Option<RData>) {
+        let mut buf = [0; 512];
+
+        let (len, peer) = socket.recv_from(&mut buf).unwrap();
+
+        let query = Message::from_vec(&buf[..len]).unwrap();
+
+        let name = query.queries()[0].name().clone();
+
+        let mut response = Message::new();
+
+        response
+            .set_id(query.id())
+            .set_message_type(MessageType::Response)
+            .set_response_code(response_code)
+            .set_recursion_desired(true)
+            .set_recursion_available(true)
+            .add_queries(query.queries().to_vec());
+
+        if let Some(answer) = answer {
+            response.add_answer(Record::from_rdata(name, 300, answer));
+        }
+
+        socket.send_to(&response.to_vec().unwrap(), peer).unwrap();
+    }
+
+    /// Creates a resolver that asks a server which answers a single query, without an async runtime.
+    fn resolver(response_code: ResponseCode, answer: Option<RData>) -> Dns {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+
+        thread::spawn(move || serve(socket, response_code, answer));
+
+        let config = ResolverConfig::from_parts(
+            None,
+            Vec::new(),
+            NameServerConfigGroup::from_ips_clear(&[IpAddr::V4(Ipv4Addr::LOCALHOST)], port, true),
+        );
+
+        block_on(Dns::with_runtime(
+            config,
+            ResolverOpts::default(),
+            Runtime::Spawner(Spawner::threads()),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_spawner_runtime() {
+        let dns = resolver(
+            ResponseCode::NoError,
+            Some(RData::MX(MX::new(
+                10,
+                Name::from_ascii("mx.example.com.").unwrap(),
+            ))),
+        );
+
+        // No async runtime is running here, the resolver drives itself.
+        let records = block_on(dns.mx("example.com.")).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].exchange(), "mx.example.com.");
+        assert!(records[0].ttl().unwrap() <= Duration::from_secs(300));
+    }
+
+    #[test]
+    fn test_no_records() {
+        let dns = resolver(ResponseCode::NXDomain, None);
+
+        let error = block_on(dns.mx("missing.example.com.")).unwrap_err();
+
+        assert!(matches!(error.kind(), ErrorKind::NoRecordsFound));
+        assert!(error.is_permanent());
+
+        let dns = resolver(ResponseCode::NoError, None);
+
+        let error = block_on(dns.txt("example.com.")).unwrap_err();
+
+        assert!(matches!(error.kind(), ErrorKind::NoRecordsFound));
+
+        // The server failing to answer is not the same as the name not having any records.
+        let dns = resolver(ResponseCode::ServFail, None);
+
+        let error = block_on(dns.srv("_imaps._tcp.example.com.")).unwrap_err();
+
+        assert!(matches!(error.kind(), ErrorKind::Resolve(_)));
+        assert!(error.is_transient());
+    }
+}
@@ -1,9 +1,15 @@
-use std::{error, fmt, result};
+use std::{error, fmt, io, result, time::Duration};
 
-use trust_dns_resolver::error::ResolveError;
+use trust_dns_resolver::{
+    error::{ResolveError, ResolveErrorKind},
+    proto::{error::ProtoErrorKind, op::ResponseCode},
+};
+
+use crate::discovery::Source;
 
 #[derive(Debug)]
 pub enum ErrorKind {
+    #[cfg(feature = "http-curl")]
     Surf(surf::Error),
     BuildHttpClient,
     InvalidResponse,
@@ -13,12 +19,19 @@ pub enum ErrorKind {
     Resolve(ResolveError),
     NotFound(Vec<Error>),
     ParseXml(serde_xml_rs::Error),
+    ParseJson(serde_json::Error),
+    UnknownPlaceholder(String),
+    InvalidConfig,
+    Insecure,
+    Io(io::Error),
 }
 
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
     message: String,
+    status: Option<u16>,
+    origin: Option<Box<(Source, Duration)>>,
 }
 
 impl Error {
@@ -26,7 +39,24 @@ impl Error {
         Self {
             kind,
             message: msg.into(),
+            status: None,
+            origin: None,
+        }
+    }
+
+    pub(crate) fn with_status(mut self, status: u16) -> Self {
+        self.status = Some(status);
+
+        self
+    }
+
+    /// Records which discovery source failed and how long it took, unless the error already knows where it came from.
+    pub(crate) fn with_origin(mut self, source: Source, elapsed: Duration) -> Self {
+        if self.origin.is_none() {
+            self.origin = Some(Box::new((source, elapsed)));
         }
+
+        self
     }
 
     pub fn kind(&self) -> &ErrorKind {
@@ -36,8 +66,134 @@ impl Error {
     pub fn message(&self) -> &str {
         &self.message
     }
+
+    /// The http status code, if the error was caused by an unsuccessful http response.
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// The discovery source that failed, if the error came from one.
+    pub fn origin(&self) -> Option<&Source> {
+        self.origin.as_deref().map(|(source, _)| source)
+    }
+
+    /// How long the discovery source took before it failed.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.origin.as_deref().map(|(_, elapsed)| *elapsed)
+    }
+
+    /// Whether the error is likely caused by a temporary problem, like a timeout, a reset connection, a server error response or a failing DNS server, so retrying could succeed.
+    ///
+    /// If no config could be found, the error is transient if any of the sources failed transiently.
+    pub fn is_transient(&self) -> bool {
+        match &self.kind {
+            ErrorKind::Timeout => true,
+            #[cfg(feature = "http-curl")]
+            ErrorKind::Surf(error) => is_transient_surf(error),
+            ErrorKind::Io(error) => is_transient_io(error),
+            ErrorKind::Resolve(error) => is_transient_resolve(error),
+            ErrorKind::NotFound(errors) => errors.iter().any(Error::is_transient),
+            _ => matches!(self.status, Some(408 | 429 | 500..=599)),
+        }
+    }
+
+    /// Whether retrying will not make a difference, like a domain that does not exist or does not publish a config. The opposite of [`Error::is_transient`].
+    pub fn is_permanent(&self) -> bool {
+        !self.is_transient()
+    }
+
+    /// A human readable report of the error, which lists every source that was tried on its own line if no config could be found.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+
+        self.write_report(&mut report, 0);
+
+        report
+    }
+
+    fn write_report(&self, report: &mut String, depth: usize) {
+        if depth > 0 {
+            report.push('\n');
+            report.push_str(&"  ".repeat(depth - 1));
+            report.push_str("- ");
+        }
+
+        if let Some((source, elapsed)) = self.origin.as_deref() {
+            report.push_str(&format!("{} failed after {:?}", source, elapsed));
+
+            if let Some(status) = self.status {
+                report.push_str(&format!(" with status {}", status));
+            }
+
+            report.push_str(": ");
+        }
+
+        report.push_str(&self.message);
+
+        if let Some(source) = error::Error::source(self) {
+            report.push_str(&format!(": {}", source));
+        }
+
+        if let ErrorKind::NotFound(errors) = &self.kind {
+            for error in errors {
+                error.write_report(report, depth + 1);
+            }
+        }
+    }
+}
+
+fn is_transient_io(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::Interrupted
+    )
+}
+
+#[cfg(feature = "http-curl")]
+fn is_transient_surf(error: &surf::Error) -> bool {
+    if let Some(error) = error.downcast_ref::<isahc::Error>() {
+        // A host that cannot be resolved or refuses the connection usually just does not exist, unrecognized curl errors are mostly failed transfers.
+        return match error {
+            isahc::Error::Io(error) => is_transient_io(error),
+            isahc::Error::Aborted
+            | isahc::Error::Curl(_)
+            | isahc::Error::NoResponse
+            | isahc::Error::RequestBodyError(_)
+            | isahc::Error::ResponseBodyError(_)
+            | isahc::Error::Timeout => true,
+            _ => false,
+        };
+    }
+
+    match error.downcast_ref::<io::Error>() {
+        Some(error) => is_transient_io(error),
+        None => false,
+    }
+}
+
+fn is_transient_resolve(error: &ResolveError) -> bool {
+    match error.kind() {
+        ResolveErrorKind::Timeout | ResolveErrorKind::NoConnections => true,
+        ResolveErrorKind::Io(error) => is_transient_io(error),
+        ResolveErrorKind::Proto(error) => match error.kind() {
+            ProtoErrorKind::Timeout | ProtoErrorKind::Busy => true,
+            ProtoErrorKind::Io(error) => is_transient_io(error),
+            _ => false,
+        },
+        // SERVFAIL means the DNS server could not answer, while NXDOMAIN means the name does not exist.
+        ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+            *response_code == ResponseCode::ServFail
+        }
+        _ => false,
+    }
 }
 
+#[cfg(feature = "http-curl")]
 impl From<surf::Error> for Error {
     fn from(error: surf::Error) -> Self {
         Self::new(ErrorKind::Surf(error), "Failed to create http request")
@@ -50,6 +206,18 @@ impl From<serde_xml_rs::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Self::new(ErrorKind::ParseJson(error), "Error parsing JSON response")
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::new(ErrorKind::Io(error), "Connection to server failed")
+    }
+}
+
 impl From<ResolveError> for Error {
     fn from(error: ResolveError) -> Self {
         Self::new(ErrorKind::Resolve(error), "Error resolving dns")
@@ -63,6 +231,12 @@ impl error::Error for Error {
 
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self.kind() {
+            #[cfg(feature = "http-curl")]
+            ErrorKind::Surf(error) => Some(error.as_ref()),
+            ErrorKind::Resolve(error) => Some(error),
+            ErrorKind::ParseXml(error) => Some(error),
+            ErrorKind::ParseJson(error) => Some(error),
+            ErrorKind::Io(error) => Some(error),
             _ => None,
         }
     }
@@ -75,3 +249,34 @@ impl fmt::Display for Error {
 }
 
 pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{Error, ErrorKind};
+
+    #[test]
+    fn test_is_transient() {
+        let timeout = Error::new(ErrorKind::Timeout, "Timed out");
+        let reset: Error = io::Error::from(io::ErrorKind::ConnectionReset).into();
+        let unavailable = Error::new(ErrorKind::InvalidResponse, "Unavailable").with_status(503);
+        let not_found = Error::new(ErrorKind::InvalidResponse, "Not found").with_status(404);
+
+        assert!(timeout.is_transient());
+        assert!(reset.is_transient());
+        assert!(unavailable.is_transient());
+        assert!(not_found.is_permanent());
+
+        let errors = Error::new(ErrorKind::NotFound(vec![not_found]), "Not found");
+
+        assert!(errors.is_permanent());
+
+        let errors = Error::new(
+            ErrorKind::NotFound(vec![timeout, errors]),
+            "Could not find a valid config",
+        );
+
+        assert!(errors.is_transient());
+    }
+}
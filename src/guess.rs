@@ -0,0 +1,270 @@
+//! Thunderbird's last resort: guessing a config by connecting to the hostnames and ports mail servers commonly use.
+
+use futures::future::join_all;
+
+use crate::{
+    config::{AuthenticationType, Config, SecurityType, Server, ServerType},
+    discovery::Trust,
+    error::{Error, ErrorKind, Result},
+    verify::{Report, Verifier},
+};
+
+const EMAIL_ADDRESS_PLACEHOLDER: &str = "%EMAILADDRESS%";
+
+const IMAP_PREFIXES: [&str; 2] = ["imap.", "mail."];
+const POP3_PREFIXES: [&str; 3] = ["pop3.", "pop.", "mail."];
+const SMTP_PREFIXES: [&str; 2] = ["smtp.", "mail."];
+
+/// The ports that are probed for each protocol, ports that use implicit TLS come first as they are preferred (RFC 8314).
+fn ports(server_type: &ServerType) -> Vec<(u16, SecurityType)> {
+    match server_type {
+        ServerType::Imap => vec![(993, SecurityType::Tls), (143, SecurityType::Plain)],
+        ServerType::Pop3 => vec![(995, SecurityType::Tls), (110, SecurityType::Plain)],
+        ServerType::Smtp => vec![
+            (465, SecurityType::Tls),
+            (587, SecurityType::Plain),
+            (25, SecurityType::Plain),
+        ],
+        ServerType::Exchange => Vec::new(),
+    }
+}
+
+/// The servers that are probed for a domain, in the order they are preferred.
+///
+/// Ports without implicit TLS are probed in plain text, whether they support STARTTLS is derived from their capabilities.
+pub fn candidates<D: AsRef<str>>(domain: D) -> Vec<Server> {
+    let domain = domain.as_ref();
+
+    let mut servers = Vec::new();
+
+    for (server_type, prefixes) in [
+        (ServerType::Imap, &IMAP_PREFIXES[..]),
+        (ServerType::Pop3, &POP3_PREFIXES[..]),
+        (ServerType::Smtp, &SMTP_PREFIXES[..]),
+    ] {
+        for (port, security_type) in ports(&server_type) {
+            for prefix in prefixes {
+                let hostname = format!("{}{}", prefix, domain);
+
+                let server = match server_type {
+                    ServerType::Imap => Server::imap(hostname, port, security_type.clone()),
+                    ServerType::Pop3 => Server::pop3(hostname, port, security_type.clone()),
+                    _ => Server::smtp(hostname, port, security_type.clone()),
+                };
+
+                servers.push(server);
+            }
+        }
+    }
+
+    servers
+}
+
+/// Whether the server offered to upgrade the plain text connection using STARTTLS.
+fn offers_starttls(report: &Report) -> bool {
+    let keyword = match report.server_type() {
+        ServerType::Pop3 => "STLS",
+        _ => "STARTTLS",
+    };
+
+    report
+        .capabilities()
+        .unwrap_or_default()
+        .iter()
+        .any(|capability| capability.eq_ignore_ascii_case(keyword))
+}
+
+/// Turns a report of a server that answered into the server that should be configured.
+fn into_server(report: &Report) -> Server {
+    let security_type = match report.security_type() {
+        SecurityType::Plain if offers_starttls(report) => SecurityType::Starttls,
+        security_type => security_type.clone(),
+    };
+
+    let mut server = match report.server_type() {
+        ServerType::Imap => Server::imap(report.hostname(), report.port(), security_type),
+        ServerType::Pop3 => Server::pop3(report.hostname(), report.port(), security_type),
+        _ => Server::smtp(report.hostname(), report.port(), security_type),
+    };
+
+    server = server.with_username(EMAIL_ADDRESS_PLACEHOLDER);
+
+    let offered: Vec<_> = report
+        .authentication_types()
+        .into_iter()
+        .filter(|authentication_type| {
+            matches!(
+                authentication_type,
+                AuthenticationType::PasswordCleartext
+                    | AuthenticationType::PasswordEncrypted
+                    | AuthenticationType::OAuth2
+            )
+        })
+        .collect();
+
+    if offered.is_empty() {
+        return server.with_authentication(AuthenticationType::PasswordCleartext);
+    }
+
+    for authentication_type in offered {
+        server = server.with_authentication(authentication_type);
+    }
+
+    server
+}
+
+/// How desirable a server is, lower is better: encrypted connections with a valid certificate are preferred.
+fn score(report: &Report) -> (u8, bool) {
+    let security = match report.security_type() {
+        SecurityType::Tls => 0,
+        SecurityType::Plain if offers_starttls(report) => 1,
+        SecurityType::Starttls => 1,
+        SecurityType::Plain => 2,
+    };
+
+    (security, report.certificate_valid() == Some(false))
+}
+
+/// Picks the best server of the given type out of the reports, which are expected in the order of [`candidates`].
+fn best<'a>(reports: &'a [Report], server_type: &ServerType) -> Option<&'a Report> {
+    reports
+        .iter()
+        .filter(|report| report.server_type() == server_type && report.is_reachable())
+        // `min_by_key` returns the first of equally scored reports, which keeps the preferred order.
+        .min_by_key(|report| score(report))
+}
+
+/// Assembles a config from the reports of the probed servers.
+pub fn into_config<D: AsRef<str>>(domain: D, reports: &[Report]) -> Result<Config> {
+    let domain = domain.as_ref();
+
+    let mut builder = Config::builder().provider(domain).domain(domain);
+
+    for server_type in [ServerType::Imap, ServerType::Pop3] {
+        if let Some(report) = best(reports, &server_type) {
+            builder = builder.incoming(into_server(report));
+        }
+    }
+
+    if let Some(report) = best(reports, &ServerType::Smtp) {
+        builder = builder.outgoing(into_server(report));
+    }
+
+    let mut config = builder.build().map_err(|_| {
+        Error::new(
+            ErrorKind::NotFound(Vec::new()),
+            "None of the common mail server hostnames answered",
+        )
+    })?;
+
+    config.set_guessed(true);
+    // Every probed hostname is a subdomain of the domain itself.
+    config.set_trust(Trust::MatchingHostnames);
+
+    Ok(config)
+}
+
+/// Probes all of the [`candidates`] for a domain concurrently and assembles a config from the servers that answered.
+pub async fn guess<D: AsRef<str>>(domain: D, verifier: &Verifier) -> Result<Config> {
+    let servers = candidates(domain.as_ref());
+
+    let reports = join_all(servers.iter().map(|server| verifier.verify_server(server))).await;
+
+    into_config(domain, &reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{AuthenticationType, SecurityType, ServerType},
+        verify::Report,
+    };
+
+    fn report(
+        hostname: &str,
+        port: u16,
+        server_type: ServerType,
+        security_type: SecurityType,
+        capabilities: &[&str],
+    ) -> Report {
+        let mut report = Report::new(hostname, port, server_type, security_type);
+
+        report.reachable = true;
+        report.capabilities = Some(capabilities.iter().map(|c| c.to_string()).collect());
+
+        report
+    }
+
+    #[test]
+    fn test_candidates() {
+        let candidates = super::candidates("example.com");
+
+        assert_eq!(candidates.len(), 4 + 6 + 6);
+        assert_eq!(candidates[0].hostname(), Some("imap.example.com"));
+        assert_eq!(candidates[0].port(), Some(&993));
+        assert_eq!(candidates[1].hostname(), Some("mail.example.com"));
+    }
+
+    #[test]
+    fn test_into_config() {
+        let mut unreachable = report(
+            "imap.example.com",
+            993,
+            ServerType::Imap,
+            SecurityType::Tls,
+            &[],
+        );
+        unreachable.reachable = false;
+
+        let reports = vec![
+            unreachable,
+            report(
+                "mail.example.com",
+                143,
+                ServerType::Imap,
+                SecurityType::Plain,
+                &["IMAP4rev1", "STARTTLS", "LOGINDISABLED"],
+            ),
+            report(
+                "smtp.example.com",
+                587,
+                ServerType::Smtp,
+                SecurityType::Plain,
+                &["PIPELINING", "AUTH PLAIN"],
+            ),
+            report(
+                "smtp.example.com",
+                25,
+                ServerType::Smtp,
+                SecurityType::Plain,
+                &["STARTTLS", "AUTH PLAIN"],
+            ),
+        ];
+
+        let config = super::into_config("example.com", &reports).unwrap();
+
+        assert!(config.is_guessed());
+
+        let provider = config.email_provider();
+
+        let incoming = provider.incoming_servers();
+
+        assert_eq!(incoming.len(), 1);
+        assert_eq!(incoming[0].hostname(), Some("mail.example.com"));
+        assert_eq!(incoming[0].security_type(), Some(&SecurityType::Starttls));
+
+        let outgoing = provider.outgoing_servers();
+
+        assert_eq!(outgoing[0].port(), Some(&25));
+        assert_eq!(outgoing[0].security_type(), Some(&SecurityType::Starttls));
+        assert_eq!(
+            outgoing[0].authentication_type(),
+            vec![&AuthenticationType::PasswordCleartext]
+        );
+    }
+
+    #[test]
+    fn test_nothing_answered() {
+        assert!(super::into_config("example.com", &[]).is_err());
+    }
+}
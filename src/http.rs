@@ -1,51 +1,77 @@
-use std::time::Duration;
+use std::{fmt::Display, time::Duration};
 
-use crate::error::{Error, ErrorKind, Result};
+use crate::{
+    error::{Error, ErrorKind, Result},
+    transport::{HttpTransport, Method, Request, Response},
+};
 
-use bytes::Bytes;
+use async_trait::async_trait;
 use surf::{Client as HttpClient, Config};
 
+/// The default [`HttpTransport`], which uses surf with a curl backend.
 pub struct Http {
     client: HttpClient,
 }
 
 impl Http {
-    const TIMEOUT: Duration = Duration::from_secs(10);
+    pub const TIMEOUT: Duration = Duration::from_secs(10);
 
     pub fn new() -> Result<Self> {
-        let client: HttpClient = Config::new()
-            .set_timeout(Some(Self::TIMEOUT))
-            .try_into()
-            .map_err(|err| {
-                Error::new(
-                    ErrorKind::BuildHttpClient,
-                    format!("Failed to create http client: {}", err),
-                )
-            })?;
+        Self::with_options(Self::TIMEOUT, None)
+    }
+
+    /// Creates a http client with the given request timeout and an optional user agent.
+    pub fn with_options(timeout: Duration, user_agent: Option<&str>) -> Result<Self> {
+        fn build_error<E: Display>(err: E) -> Error {
+            Error::new(
+                ErrorKind::BuildHttpClient,
+                format!("Failed to create http client: {}", err),
+            )
+        }
+
+        let mut config = Config::new().set_timeout(Some(timeout));
+
+        if let Some(user_agent) = user_agent {
+            config = config
+                .add_header("User-Agent", user_agent)
+                .map_err(build_error)?;
+        }
+
+        let client: HttpClient = config.try_into().map_err(build_error)?;
 
         Ok(Self { client })
     }
+}
 
-    /// Fetches a given url and returns the XML response (if there is one)
-    pub async fn get<S: AsRef<str>>(&self, uri: S) -> Result<Bytes> {
-        let mut response = self.client.get(uri.as_ref()).send().await?;
+#[async_trait]
+impl HttpTransport for Http {
+    async fn send(&self, request: Request) -> Result<Response> {
+        let mut builder = match request.method() {
+            Method::Get => self.client.get(request.url()),
+            Method::Post => self.client.post(request.url()),
+        };
+
+        if let Some(content_type) = request.content_type() {
+            builder = builder.content_type(content_type);
+        }
+
+        if let Some(body) = request.body() {
+            builder = builder.body_string(body.to_string());
+        }
 
-        let is_success = response.status().is_success();
+        let mut response = builder.send().await?;
 
         // Get the http message body
         let bytes = response.body_bytes().await?;
 
-        // If we got an error response we return an error
-        if !is_success {
-            return Err(Error::new(
-                ErrorKind::InvalidResponse,
-                format!(
-                    "Http request failed: {}",
-                    String::from_utf8(bytes.into()).unwrap()
-                ),
-            ));
-        } else {
-            return Ok(bytes.into());
-        };
+        let mut result = Response::new(response.status().into(), bytes);
+
+        for (name, values) in response.iter() {
+            for value in values {
+                result = result.with_header(name.as_str(), value.as_str());
+            }
+        }
+
+        Ok(result)
     }
 }
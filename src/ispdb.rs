@@ -0,0 +1,340 @@
+//! Offline snapshots of Mozilla's ISPDB, for when `autoconfig.thunderbird.net` can not be reached.
+//!
+//! With the `bundled-ispdb` feature a checkout of the ISPDB is embedded in the binary at build time, see `Snapshot::bundled`.
+//! An up to date snapshot can be loaded at runtime from a checkout of the ISPDB with [`Snapshot::from_dir`], or from an archive of one with `Snapshot::from_archive` if the `ispdb-archive` feature is enabled.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    address,
+    config::Config,
+    discovery::Trust,
+    error::{Error, ErrorKind, Result},
+    parse, utils,
+};
+
+#[cfg(feature = "ispdb-archive")]
+use std::io::{BufRead, BufReader, Read};
+
+/// The files of the ISPDB checkout that was embedded by the build script.
+#[cfg(feature = "bundled-ispdb")]
+const BUNDLED: &[&[u8]] = include!(concat!(env!("OUT_DIR"), "/ispdb.rs"));
+
+/// When the client consults its snapshot, see [`crate::ClientBuilder::snapshot_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SnapshotMode {
+    /// Return the config from the snapshot without querying any network source, if the snapshot lists the domain.
+    First,
+    /// Only use the snapshot when none of the network sources returned a config.
+    #[default]
+    Fallback,
+}
+
+/// A collection of ISPDB configs, indexed by the domains they list and by the domains of their servers, which is used to match mail exchangers.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    configs: Vec<Config>,
+    domains: HashMap<String, usize>,
+    hostnames: HashMap<String, usize>,
+}
+
+impl Snapshot {
+    /// Creates an empty snapshot.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The ISPDB that was embedded at build time with the `bundled-ispdb` feature.
+    ///
+    /// The crate does not ship the ISPDB itself: the build reads a checkout of the `ispdb` directory of <https://github.com/thunderbird/autoconfig> from `AUTOCONFIG_ISPDB_DIR`, or from an `ispdb` directory in the crate's root.
+    /// The ISPDB is licensed under the Mozilla Public License 2.0, so applications that ship it must credit it and tell their users where its source can be found.
+    /// Files that are not a valid config are skipped.
+    #[cfg(feature = "bundled-ispdb")]
+    pub fn bundled() -> Self {
+        let mut snapshot = Self::new();
+
+        for bytes in BUNDLED {
+            if let Ok(config) = parse::from_bytes(bytes) {
+                snapshot.insert(config);
+            }
+        }
+
+        snapshot
+    }
+
+    /// Loads every config in a directory, like the `ispdb` directory of Thunderbird's autoconfig repository.
+    ///
+    /// Files that are not a valid config, like a readme, are skipped. Files are added in the order of their names.
+    pub fn from_dir<P: AsRef<Path>>(directory: P) -> Result<Self> {
+        let mut snapshot = Self::new();
+
+        let mut paths = Vec::new();
+
+        for entry in fs::read_dir(directory).map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+
+        paths.sort();
+
+        for path in paths {
+            let bytes = fs::read(&path).map_err(io_error)?;
+
+            if let Ok(config) = parse::from_bytes(bytes) {
+                snapshot.insert(config);
+            }
+        }
+
+        snapshot.non_empty()
+    }
+
+    /// Loads every config in a tar archive, which may be gzip compressed.
+    ///
+    /// Files that are not a valid config, like a readme, are skipped.
+    #[cfg(feature = "ispdb-archive")]
+    pub fn from_archive<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+
+        let gzipped = reader
+            .fill_buf()
+            .map_err(io_error)?
+            .starts_with(&[0x1f, 0x8b]);
+
+        let reader: Box<dyn Read> = if gzipped {
+            Box::new(flate2::read::GzDecoder::new(reader))
+        } else {
+            Box::new(reader)
+        };
+
+        let mut archive = tar::Archive::new(reader);
+        let mut snapshot = Self::new();
+
+        for entry in archive.entries().map_err(io_error)? {
+            let mut entry = entry.map_err(io_error)?;
+
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let mut bytes = Vec::new();
+
+            entry.read_to_end(&mut bytes).map_err(io_error)?;
+
+            if let Ok(config) = parse::from_bytes(bytes) {
+                snapshot.insert(config);
+            }
+        }
+
+        snapshot.non_empty()
+    }
+
+    fn non_empty(self) -> Result<Self> {
+        if self.is_empty() {
+            return Err(Error::new(
+                ErrorKind::BadInput,
+                "Snapshot does not contain any configs",
+            ));
+        }
+
+        Ok(self)
+    }
+
+    /// Adds a config, replacing any config that was added before for the same domains.
+    pub fn insert(&mut self, mut config: Config) {
+        config.set_trust(Trust::Secure);
+
+        let index = self.configs.len();
+        let provider = config.email_provider();
+
+        for domain in provider.domain() {
+            self.domains
+                .insert(address::listed_domain_to_ascii(domain), index);
+        }
+
+        for server in provider.servers() {
+            match server.hostname() {
+                // Providers can share the servers of another, e.g. Hotmail and Outlook.com both use office365.com, so the first one keeps them.
+                Some(hostname) if !hostname.contains('%') => {
+                    self.hostnames
+                        .entry(utils::base_domain(hostname).to_ascii_lowercase())
+                        .or_insert(index);
+                }
+                _ => {}
+            }
+        }
+
+        self.configs.push(config);
+    }
+
+    /// The amount of configs in the snapshot.
+    pub fn len(&self) -> usize {
+        self.configs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.configs.is_empty()
+    }
+
+    /// The config of the provider that lists the domain.
+    pub fn get_by_domain<D: AsRef<str>>(&self, domain: D) -> Option<&Config> {
+        let domain = address::normalize_domain(domain.as_ref());
+
+        self.domains.get(&domain).map(|index| &self.configs[*index])
+    }
+
+    /// The config of the provider that hosts a mail exchanger, e.g. `aspmx.l.google.com` leads to Google Mail.
+    ///
+    /// Like the MX lookup against the online ISPDB, this tries the exchanger without its own hostname label first and then its base domain, after which it falls back to the domains of the providers' servers.
+    pub fn get_by_mx<E: AsRef<str>>(&self, exchange: E) -> Option<&Config> {
+        let exchange = exchange.as_ref().trim_end_matches('.').to_ascii_lowercase();
+
+        let mut mx_domains = Vec::new();
+
+        if let Some((_, parent)) = exchange.split_once('.') {
+            mx_domains.push(parent);
+        }
+
+        mx_domains.push(utils::base_domain(&exchange));
+
+        mx_domains
+            .iter()
+            .find_map(|mx_domain| self.get_by_domain(mx_domain))
+            .or_else(|| {
+                mx_domains
+                    .iter()
+                    .find_map(|mx_domain| self.hostnames.get(*mx_domain))
+                    .map(|index| &self.configs[*index])
+            })
+    }
+}
+
+fn io_error(error: std::io::Error) -> Error {
+    Error::new(ErrorKind::Io(error), "Failed to read the snapshot")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::config::{Config, SecurityType, Server};
+
+    use super::Snapshot;
+
+    const CONFIG: &str = r#"
+        <clientConfig version="1.1">
+            <emailProvider id="example.com">
+                <domain>example.com</domain>
+                <domain>example.net</domain>
+                <incomingServer type="imap">
+                    <hostname>imap.mailhost.org</hostname>
+                    <port>993</port>
+                    <socketType>SSL</socketType>
+                </incomingServer>
+            </emailProvider>
+        </clientConfig>
+    "#;
+
+    #[test]
+    fn test_lookup() {
+        let mut snapshot = Snapshot::new();
+
+        snapshot.insert(Config::from_xml(CONFIG).unwrap());
+
+        assert!(snapshot.get_by_domain("Example.NET.").is_some());
+        assert!(snapshot.get_by_domain("example.org").is_none());
+
+        assert!(snapshot.get_by_mx("mx1.example.com").is_some());
+        assert!(snapshot.get_by_mx("mx.eu.mailhost.org").is_some());
+        assert!(snapshot.get_by_mx("mx.example.org").is_none());
+
+        snapshot.insert(
+            Config::builder()
+                .provider("bücher.de")
+                .domain("Bücher.de")
+                .incoming(Server::imap("imap.example.com", 993, SecurityType::Tls))
+                .build()
+                .unwrap(),
+        );
+
+        assert!(snapshot.get_by_domain("xn--bcher-kva.de").is_some());
+        assert!(snapshot.get_by_domain("bücher.de").is_some());
+
+        // The provider that was added first keeps the servers it shares with a later one.
+        snapshot.insert(Config::from_xml(CONFIG.replace("example.com", "example.org")).unwrap());
+
+        assert_eq!(
+            snapshot
+                .get_by_mx("mx.eu.mailhost.org")
+                .unwrap()
+                .email_provider()
+                .id(),
+            "example.com"
+        );
+    }
+
+    #[test]
+    fn test_from_dir() {
+        let directory = env::temp_dir().join(format!("autoconfig-ispdb-{}", std::process::id()));
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("example.com"), CONFIG).unwrap();
+        fs::write(directory.join("README.md"), "# ISPDB").unwrap();
+
+        let snapshot = Snapshot::from_dir(&directory).unwrap();
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(
+            snapshot
+                .get_by_domain("example.com")
+                .unwrap()
+                .email_provider()
+                .id(),
+            "example.com"
+        );
+    }
+
+    #[cfg(feature = "ispdb-archive")]
+    #[test]
+    fn test_from_archive() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(CONFIG.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+
+        builder
+            .append_data(&mut header, "ispdb/example.com", CONFIG.as_bytes())
+            .unwrap();
+
+        let archive = builder.into_inner().unwrap().finish().unwrap();
+
+        let snapshot = Snapshot::from_archive(archive.as_slice()).unwrap();
+
+        assert!(snapshot.get_by_domain("example.net").is_some());
+    }
+
+    #[cfg(feature = "bundled-ispdb")]
+    #[test]
+    fn test_bundled() {
+        let snapshot = Snapshot::bundled();
+
+        assert!(!snapshot.is_empty());
+
+        assert_eq!(
+            snapshot
+                .get_by_domain("gmail.com")
+                .unwrap()
+                .email_provider()
+                .id(),
+            "googlemail.com"
+        );
+    }
+}
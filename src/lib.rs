@@ -41,104 +41,115 @@
 //!
 //! ```
 //!
+//! With the `blocking` feature enabled, the same functions are available without an async runtime in the [`blocking`] module:
+//!
+//! ```rust,ignore
+//!
+//! extern crate autoconfig;
+//!
+//! fn main() {
+//!     let config = autoconfig::blocking::from_addr("test@gmail.com").unwrap();
+//!
+//!     println!("{}", config.email_provider().id())
+//! }
+//!
+//! ```
+//!
 
-use client::Client;
-use futures::{future::select_ok, FutureExt};
-use utils::validate_email;
-
+pub mod address;
+mod autodiscover;
+mod autodiscover_v2;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cache;
 mod client;
 pub mod config;
+pub mod discovery;
 mod dns;
 pub mod error;
+#[cfg(feature = "verify")]
+mod guess;
+#[cfg(feature = "http-curl")]
 mod http;
+pub mod ispdb;
 mod parse;
+mod placeholder;
+pub mod registry;
+mod runtime;
+mod srv;
+pub mod transport;
 mod utils;
-
-const AT_SYMBOL: char = '@';
-
+pub mod validate;
+#[cfg(feature = "verify")]
+pub mod verify;
+mod write;
+
+pub use client::{Client, ClientBuilder};
+pub use dns::Dns;
+#[cfg(feature = "http-curl")]
+pub use http::Http;
+pub use runtime::{Runtime, Spawner};
+pub use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+
+#[cfg(feature = "http-curl")]
 use config::Config;
-use error::{Error, ErrorKind, Result};
+#[cfg(feature = "http-curl")]
+use discovery::{Candidate, Strategy};
+#[cfg(feature = "http-curl")]
+use error::Result;
 
 /// Given an email providers domain, try to connect to autoconfig servers for that provider and return the config.
+///
+/// When multiple sources return a config, the one from the most trustworthy source is returned, see [`Strategy::default`].
+///
+/// This creates a new [`Client`] for every call, use [`Client::lookup_domain`] to reuse one for many lookups.
+#[cfg(feature = "http-curl")]
 pub async fn from_domain<D: AsRef<str>>(domain: D) -> Result<Config> {
-    let mut errors: Vec<_> = Vec::new();
-
-    let client = Client::new().await?;
-
-    let mut futures = Vec::new();
-
-    let mut urls = vec![
-        // Try connect to connect with the users mail server directly
-        format!("http://autoconfig.{}/mail/config-v1.1.xml", domain.as_ref()),
-        // The fallback url
-        format!(
-            "http://{}/.well-known/autoconfig/mail/config-v1.1.xml",
-            domain.as_ref()
-        ),
-        // If the previous two methods did not work then the email server provider has not setup Thunderbird autoconfig, so we ask Mozilla for their config.
-        format!(
-            "https://autoconfig.thunderbird.net/v1.1/{}",
-            domain.as_ref()
-        ),
-    ];
-
-    match client.get_url_from_txt(domain.as_ref()).await {
-        Ok(txt_urls) => {
-            for url in txt_urls {
-                urls.push(url)
-            }
-        }
-        Err(error) => errors.push(error),
-    };
-
-    urls.sort();
-    urls.dedup();
-
-    for url in urls {
-        let future = client.get_config(url);
+    from_domain_with_strategy(domain, Strategy::default()).await
+}
 
-        futures.push(future.boxed());
-    }
+/// The same as [`from_domain`], but with control over which config is returned when multiple sources return one.
+#[cfg(feature = "http-curl")]
+pub async fn from_domain_with_strategy<D: AsRef<str>>(
+    domain: D,
+    strategy: Strategy,
+) -> Result<Config> {
+    let client = Client::builder().strategy(strategy).build().await?;
 
-    let result = select_ok(futures).await;
+    client.lookup_domain(domain).await
+}
 
-    match result {
-        Ok((config, _remaining)) => return Ok(config),
-        Err(error) => errors.push(error),
-    }
+/// Given an email providers domain, query every discovery source and return all of their outcomes, instead of just the first config that was found.
+///
+/// The candidates are ranked by [`discovery::Candidate::rank`]: sources that returned a config come first, ordered by the trustworthiness of their source, which is the order in which the variants of [`discovery::Mechanism`] are declared: from the client's own registry and the provider's autoconfig server down to the offline snapshot and a guess of common hostnames.
+#[cfg(feature = "http-curl")]
+pub async fn discover_all<D: AsRef<str>>(domain: D) -> Result<Vec<Candidate>> {
+    let client = Client::new().await?;
 
-    Err(Error::new(
-        ErrorKind::NotFound(errors),
-        "Could not find a valid config",
-    ))
+    Ok(client.discover_all(domain).await)
 }
 
 /// Given an email address, try to connect to the email providers autoconfig servers and return the config that was found, if one was found.
+///
+/// When multiple sources return a config, the one from the most trustworthy source is returned, see [`Strategy::default`].
+///
+/// This creates a new [`Client`] for every call, use [`Client::lookup_addr`] to reuse one for many lookups.
+#[cfg(feature = "http-curl")]
 pub async fn from_addr(email_address: &str) -> Result<Config> {
-    if !validate_email(email_address) {
-        return Err(Error::new(
-            ErrorKind::BadInput,
-            "Given email address is invalid",
-        ));
-    };
-
-    let mut split = email_address.split(AT_SYMBOL);
-
-    // Skip the prefix
-    split.next();
+    from_addr_with_strategy(email_address, Strategy::default()).await
+}
 
-    let domain = match split.next() {
-        Some(domain) => domain,
-        None => {
-            return Err(Error::new(
-                ErrorKind::BadInput,
-                "An email address must specify a domain after the '@' symbol",
-            ))
-        }
-    };
+/// The same as [`from_addr`], but with control over which config is returned when multiple sources return one.
+#[cfg(feature = "http-curl")]
+pub async fn from_addr_with_strategy(email_address: &str, strategy: Strategy) -> Result<Config> {
+    let client = Client::builder().strategy(strategy).build().await?;
 
-    from_domain(domain).await
+    client.lookup_addr(email_address).await
 }
 
-#[cfg(test)]
+#[cfg(all(
+    test,
+    feature = "http-curl",
+    any(feature = "runtime-tokio", feature = "runtime-async-std")
+))]
 mod test;
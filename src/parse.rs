@@ -7,7 +7,10 @@ use crate::{config::Config, error::Result};
 pub fn from_bytes<B: AsRef<[u8]>>(bytes: B) -> Result<Config> {
     let reader = io::Cursor::new(bytes.as_ref());
 
-    let config: Config = serde_xml_rs::from_reader(reader)?;
+    let mut config: Config = serde_xml_rs::from_reader(reader)?;
+
+    // The metadata is only meant for the JSON format, where it is written by the application itself.
+    config.clear_metadata();
 
     Ok(config)
 }
@@ -0,0 +1,156 @@
+//! Substitution of the placeholders Thunderbird allows in usernames, hostnames and urls, see: https://wiki.mozilla.org/Thunderbird:Autoconfiguration:ConfigFileFormat#Placeholders
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::{Captures, Regex};
+
+use crate::{
+    address::EmailAddress,
+    error::{Error, ErrorKind, Result},
+};
+
+/// Matches `%NAME%`, requiring at least three characters so percent encoded urls like `%2F%3A` are left alone.
+const PLACEHOLDER_REGEX: &str = r"%([A-Z][A-Z_]{2,})%";
+
+/// Everything but the unreserved characters of RFC 3986, so a value that is inserted into a url can not change its structure.
+const URL_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+pub struct Placeholders<'a> {
+    email_address: &'a str,
+    address: EmailAddress,
+    real_name: &'a str,
+}
+
+impl<'a> Placeholders<'a> {
+    pub fn new(email_address: &'a str, real_name: Option<&'a str>) -> Result<Self> {
+        Ok(Self {
+            email_address,
+            address: EmailAddress::parse(email_address)?,
+            real_name: real_name.unwrap_or_default(),
+        })
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        match name {
+            "EMAILADDRESS" => Some(self.email_address),
+            "EMAILLOCALPART" => Some(self.address.local_part()),
+            "EMAILDOMAIN" => Some(self.address.domain()),
+            "REALNAME" => Some(self.real_name),
+            _ => None,
+        }
+    }
+
+    /// Replaces all of the placeholders in the given text, failing if it contains a placeholder we don't know.
+    pub fn substitute(&self, text: &str) -> Result<String> {
+        self.replace(text, false)
+    }
+
+    /// Replaces all of the placeholders in the given url like [`Placeholders::substitute`], percent encoding the values that are inserted.
+    pub fn substitute_url(&self, url: &str) -> Result<String> {
+        self.replace(url, true)
+    }
+
+    fn replace(&self, text: &str, encode: bool) -> Result<String> {
+        let re = Regex::new(PLACEHOLDER_REGEX).unwrap();
+
+        if let Some(unknown) = re
+            .captures_iter(text)
+            .map(|captured| captured[1].to_string())
+            .find(|name| self.value(name).is_none())
+        {
+            return Err(Error::new(
+                ErrorKind::UnknownPlaceholder(unknown.clone()),
+                format!("Config contains an unknown placeholder: %{}%", unknown),
+            ));
+        }
+
+        let replaced = re.replace_all(text, |captured: &Captures| {
+            let value = self.value(&captured[1]).unwrap_or_default();
+
+            if encode {
+                utf8_percent_encode(value, URL_COMPONENT).to_string()
+            } else {
+                value.to_string()
+            }
+        });
+
+        Ok(replaced.into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorKind;
+
+    use super::Placeholders;
+
+    #[test]
+    fn test_substitute() {
+        let placeholders = Placeholders::new("john.doe@example.com", Some("John Doe")).unwrap();
+
+        assert_eq!(
+            placeholders.substitute("%EMAILLOCALPART%").unwrap(),
+            "john.doe"
+        );
+        assert_eq!(
+            placeholders
+                .substitute("%REALNAME% <%EMAILADDRESS%>")
+                .unwrap(),
+            "John Doe <john.doe@example.com>"
+        );
+        assert_eq!(
+            placeholders
+                .substitute_url("https://%EMAILDOMAIN%/mail?user=%EMAILADDRESS%&name=%REALNAME%")
+                .unwrap(),
+            "https://example.com/mail?user=john.doe%40example.com&name=John%20Doe"
+        );
+        assert_eq!(
+            placeholders
+                .substitute_url("https://example.com/a%2F%3Ab")
+                .unwrap(),
+            "https://example.com/a%2F%3Ab"
+        );
+
+        // A value can not add query parameters or path segments to a url.
+        let placeholders = Placeholders::new(r#""a&admin=1/..#"@example.com"#, None).unwrap();
+
+        assert_eq!(
+            placeholders
+                .substitute_url("https://example.com/owa/?user=%EMAILLOCALPART%")
+                .unwrap(),
+            "https://example.com/owa/?user=%22a%26admin%3D1%2F..%23%22"
+        );
+
+        // The domain is inserted in its ASCII form, as it is used in hostnames and urls.
+        let placeholders = Placeholders::new("müller@Bücher.de", None).unwrap();
+
+        assert_eq!(
+            placeholders
+                .substitute_url("https://autoconfig.%EMAILDOMAIN%/?user=%EMAILLOCALPART%")
+                .unwrap(),
+            "https://autoconfig.xn--bcher-kva.de/?user=m%C3%BCller"
+        );
+        assert_eq!(
+            placeholders.substitute("imap.%EMAILDOMAIN%").unwrap(),
+            "imap.xn--bcher-kva.de"
+        );
+
+        assert!(Placeholders::new("john.doe@", None).is_err());
+        assert!(Placeholders::new("john doe@example.com", None).is_err());
+    }
+
+    #[test]
+    fn test_unknown_placeholder() {
+        let placeholders = Placeholders::new("john.doe@example.com", None).unwrap();
+
+        let error = placeholders.substitute("%EMAILUSER%").unwrap_err();
+
+        match error.kind() {
+            ErrorKind::UnknownPlaceholder(name) => assert_eq!(name, "EMAILUSER"),
+            kind => panic!("Unexpected error kind: {:?}", kind),
+        }
+    }
+}
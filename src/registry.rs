@@ -0,0 +1,239 @@
+//! Configs provided by the application itself, to support internal domains or to correct wrong entries of the ISPDB without waiting for it to be fixed.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    address,
+    config::Config,
+    discovery::Trust,
+    error::{Error, ErrorKind, Result},
+    parse, utils,
+};
+
+/// A collection of configs that are returned for the domains they list, before any network source is queried.
+///
+/// A listed domain can be a wildcard like `*.example.com`, which matches every subdomain of `example.com`. A domain that is listed exactly takes precedence over a wildcard, and a more specific wildcard over a less specific one.
+/// When multiple configs list the same domain, the one that was added last is used.
+#[derive(Debug, Clone, Default)]
+pub struct ProviderRegistry {
+    /// Every config together with the domains it lists, in their ASCII form.
+    configs: Vec<(Vec<String>, Config)>,
+}
+
+impl ProviderRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads every `.xml` file in a directory and its subdirectories, like `<domain>/config-v1.1.xml`.
+    ///
+    /// Files are added in the order of their paths, so a later path overrides an earlier one.
+    pub fn from_dir<P: AsRef<Path>>(directory: P) -> Result<Self> {
+        let mut registry = Self::new();
+
+        registry.insert_dir(directory)?;
+
+        Ok(registry)
+    }
+
+    /// Adds a config, internationalized domains it lists are matched in their ASCII form.
+    pub fn insert(&mut self, mut config: Config) {
+        config.set_trust(Trust::Secure);
+
+        let domains = config
+            .email_provider()
+            .domain()
+            .into_iter()
+            .map(address::listed_domain_to_ascii)
+            .collect();
+
+        self.configs.push((domains, config));
+    }
+
+    /// Adds a config in Thunderbird's XML format.
+    pub fn insert_xml<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<()> {
+        self.insert(parse::from_bytes(bytes)?);
+
+        Ok(())
+    }
+
+    /// Adds the config in a file in Thunderbird's XML format.
+    pub fn insert_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+
+        let bytes = fs::read(path).map_err(io_error)?;
+
+        let config = parse::from_bytes(bytes).map_err(|error| {
+            Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Failed to parse {}: {}", path.display(), error),
+            )
+        })?;
+
+        self.insert(config);
+
+        Ok(())
+    }
+
+    /// Adds every `.xml` file in a directory and its subdirectories, see [`ProviderRegistry::from_dir`].
+    pub fn insert_dir<P: AsRef<Path>>(&mut self, directory: P) -> Result<()> {
+        let mut paths = Vec::new();
+
+        collect_xml_files(directory.as_ref(), &mut paths)?;
+
+        paths.sort();
+
+        for path in paths {
+            self.insert_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// The amount of configs in the registry.
+    pub fn len(&self) -> usize {
+        self.configs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.configs.is_empty()
+    }
+
+    /// The config that lists the domain, either exactly or with the most specific wildcard.
+    ///
+    /// Like the listed domains, the domain is matched in its ASCII form and without a trailing dot.
+    pub fn get<D: AsRef<str>>(&self, domain: D) -> Option<&Config> {
+        let domain = address::normalize_domain(domain.as_ref());
+        let domain = domain.as_str();
+
+        let mut best: Option<(usize, &Config)> = None;
+
+        for (domains, config) in self.configs.iter().rev() {
+            for listed in domains {
+                if !utils::matches_domain(listed, domain) {
+                    continue;
+                }
+
+                // An exact match has no wildcard label, which makes it the most specific.
+                let specificity = match listed.strip_prefix("*.") {
+                    Some(parent) => parent.split('.').count(),
+                    None => usize::MAX,
+                };
+
+                if best.map_or(true, |(best, _)| specificity > best) {
+                    best = Some((specificity, config));
+                }
+            }
+        }
+
+        best.map(|(_, config)| config)
+    }
+}
+
+fn collect_xml_files(directory: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(directory).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+
+        if path.is_dir() {
+            collect_xml_files(&path, paths)?;
+        } else if path
+            .extension()
+            .map_or(false, |extension| extension == "xml")
+        {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn io_error(error: std::io::Error) -> Error {
+    Error::new(ErrorKind::Io(error), "Failed to read the registry")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::config::{Config, SecurityType, Server};
+
+    use super::ProviderRegistry;
+
+    fn config(id: &str, domain: &str) -> Config {
+        Config::builder()
+            .provider(id)
+            .domain(domain)
+            .incoming(Server::imap("imap.example.com", 993, SecurityType::Tls))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_matching() {
+        let mut registry = ProviderRegistry::new();
+
+        registry.insert(config("wildcard", "*.example.com"));
+        registry.insert(config("eu", "*.eu.example.com"));
+        registry.insert(config("exact", "mail.eu.example.com"));
+        registry.insert(config("idn", "*.Bücher.de"));
+        registry.insert(config("exact-idn", "bücher.de"));
+
+        let id = |domain| {
+            registry
+                .get(domain)
+                .map(|config| config.email_provider().id())
+        };
+
+        assert_eq!(id("Mail.EU.example.com"), Some("exact"));
+        assert_eq!(id("office.eu.example.com"), Some("eu"));
+        assert_eq!(id("us.example.com"), Some("wildcard"));
+        assert_eq!(id("example.com"), None);
+        assert_eq!(id("shop.xn--bcher-kva.de"), Some("idn"));
+        assert_eq!(id("Shop.Bücher.de."), Some("idn"));
+        assert_eq!(id("us.example.com."), Some("wildcard"));
+        assert_eq!(id("Bücher.de."), Some("exact-idn"));
+        assert_eq!(id("xn--bcher-kva.de"), Some("exact-idn"));
+
+        registry.insert(config("override", "us.example.com"));
+
+        assert_eq!(
+            registry
+                .get("us.example.com")
+                .unwrap()
+                .email_provider()
+                .id(),
+            "override"
+        );
+    }
+
+    #[test]
+    fn test_from_dir() {
+        let directory = env::temp_dir().join(format!("autoconfig-registry-{}", std::process::id()));
+
+        fs::create_dir_all(directory.join("example.com")).unwrap();
+        fs::write(
+            directory.join("example.com").join("config-v1.1.xml"),
+            config("example.com", "example.com").to_xml(),
+        )
+        .unwrap();
+        fs::write(directory.join("README.md"), "# Overrides").unwrap();
+
+        let registry = ProviderRegistry::from_dir(&directory);
+
+        fs::write(directory.join("broken.xml"), "<clientConfig>").unwrap();
+
+        let broken = ProviderRegistry::from_dir(&directory);
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        let registry = registry.unwrap();
+
+        assert_eq!(registry.len(), 1);
+        assert!(registry.get("example.com").is_some());
+        assert!(broken.is_err());
+    }
+}
@@ -0,0 +1,280 @@
+//! The async runtimes the default DNS resolver can run on.
+//!
+//! Besides tokio and async-std, the resolver can run on any executor: its sockets are driven by async-io and its background tasks are handed to a [`Spawner`].
+
+use std::{
+    fmt, io,
+    net::{self, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+
+use async_io::Async;
+use async_trait::async_trait;
+use futures::{
+    channel::oneshot,
+    executor::block_on,
+    future::{select, BoxFuture, Either, Future},
+    io::{AsyncRead, AsyncWrite},
+    FutureExt,
+};
+use futures_timer::Delay;
+use socket2::{Domain, Protocol, Socket, Type};
+use trust_dns_resolver::{
+    name_server::{RuntimeProvider, Spawn},
+    proto::{
+        error::ProtoError,
+        tcp::{Connect, DnsTcpStream},
+        udp::UdpSocket,
+        Time,
+    },
+};
+
+/// Spawns the background tasks of the DNS resolver onto an executor.
+#[derive(Clone)]
+pub struct Spawner {
+    spawn: Arc<dyn Fn(BoxFuture<'static, ()>) + Send + Sync>,
+}
+
+impl Spawner {
+    /// Uses the given function to spawn background tasks, for example `|task| smol::spawn(task).detach()`.
+    ///
+    /// The tasks must be polled to completion, or lookups will never finish.
+    pub fn new<F: Fn(BoxFuture<'static, ()>) + Send + Sync + 'static>(spawn: F) -> Self {
+        Self {
+            spawn: Arc::new(spawn),
+        }
+    }
+
+    /// Runs every background task on a thread of its own, which does not need an async runtime at all.
+    pub fn threads() -> Self {
+        Self::new(|task| {
+            thread::spawn(move || block_on(task));
+        })
+    }
+}
+
+impl Default for Spawner {
+    fn default() -> Self {
+        Self::threads()
+    }
+}
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spawner").finish_non_exhaustive()
+    }
+}
+
+impl Spawn for Spawner {
+    fn spawn_bg<F>(&mut self, future: F)
+    where
+        F: Future<Output = std::result::Result<(), ProtoError>> + Send + 'static,
+    {
+        (self.spawn)(future.map(|_| ()).boxed())
+    }
+}
+
+/// The runtime the default DNS resolver runs on, see [`crate::ClientBuilder::runtime`].
+#[derive(Debug, Clone)]
+pub enum Runtime {
+    /// Uses tokio, which requires the lookups to be run from within a tokio runtime.
+    #[cfg(feature = "runtime-tokio")]
+    Tokio,
+    /// Uses async-std.
+    #[cfg(feature = "runtime-async-std")]
+    AsyncStd,
+    /// Uses async-io for its sockets and the given spawner for its background tasks, which works with any executor.
+    Spawner(Spawner),
+}
+
+impl Default for Runtime {
+    /// Uses tokio if it is enabled and a tokio runtime is running, then async-std if it is enabled and otherwise the default [`Spawner`].
+    fn default() -> Self {
+        #[cfg(feature = "runtime-tokio")]
+        if tokio::runtime::Handle::try_current().is_ok() {
+            return Self::Tokio;
+        }
+
+        #[cfg(feature = "runtime-async-std")]
+        return Self::AsyncStd;
+
+        #[allow(unreachable_code)]
+        Self::Spawner(Spawner::default())
+    }
+}
+
+/// The trust-dns runtime for [`Runtime::Spawner`].
+#[derive(Clone, Copy)]
+pub(crate) struct SpawnerRuntime;
+
+impl RuntimeProvider for SpawnerRuntime {
+    type Handle = Spawner;
+    type Timer = Timer;
+    type Udp = AsyncUdpSocket;
+    type Tcp = AsyncTcpStream;
+}
+
+pub(crate) struct Timer;
+
+#[async_trait]
+impl Time for Timer {
+    async fn delay_for(duration: Duration) {
+        Delay::new(duration).await
+    }
+
+    async fn timeout<F: 'static + Future + Send>(
+        duration: Duration,
+        future: F,
+    ) -> io::Result<F::Output> {
+        match select(future.boxed(), Delay::new(duration)).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "future timed out")),
+        }
+    }
+}
+
+pub(crate) struct AsyncUdpSocket(Async<net::UdpSocket>);
+
+fn unspecified(addr: &SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(_) => (net::Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+#[async_trait]
+impl UdpSocket for AsyncUdpSocket {
+    type Time = Timer;
+
+    async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        Self::connect_with_bind(addr, unspecified(&addr)).await
+    }
+
+    async fn connect_with_bind(addr: SocketAddr, bind_addr: SocketAddr) -> io::Result<Self> {
+        let socket = Async::<net::UdpSocket>::bind(bind_addr)?;
+
+        socket.get_ref().connect(addr)?;
+
+        Ok(Self(socket))
+    }
+
+    async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self(Async::<net::UdpSocket>::bind(addr)?))
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+        loop {
+            match self.0.get_ref().recv_from(buf) {
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    match self.0.poll_readable(cx) {
+                        Poll::Ready(Ok(())) => continue,
+                        Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            match self.0.get_ref().send_to(buf, target) {
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    match self.0.poll_writable(cx) {
+                        Poll::Ready(Ok(())) => continue,
+                        Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+}
+
+pub(crate) struct AsyncTcpStream(Async<net::TcpStream>);
+
+impl DnsTcpStream for AsyncTcpStream {
+    type Time = Timer;
+}
+
+/// Connects from a specific local address, which the standard library does not support, so it is done on a separate thread.
+async fn connect_with_bind(addr: SocketAddr, bind_addr: SocketAddr) -> io::Result<net::TcpStream> {
+    let (sender, receiver) = oneshot::channel();
+
+    thread::spawn(move || {
+        let connect = || {
+            let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+            socket.bind(&bind_addr.into())?;
+            socket.connect(&addr.into())?;
+
+            io::Result::Ok(net::TcpStream::from(socket))
+        };
+
+        let _ = sender.send(connect());
+    });
+
+    receiver
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "Connecting was aborted"))?
+}
+
+#[async_trait]
+impl Connect for AsyncTcpStream {
+    async fn connect_with_bind(
+        addr: SocketAddr,
+        bind_addr: Option<SocketAddr>,
+    ) -> io::Result<Self> {
+        let stream = match bind_addr {
+            Some(bind_addr) => Async::new(connect_with_bind(addr, bind_addr).await?)?,
+            None => Async::<net::TcpStream>::connect(addr).await?,
+        };
+
+        stream.get_ref().set_nodelay(true)?;
+
+        Ok(Self(stream))
+    }
+}
+
+impl AsyncRead for AsyncTcpStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for AsyncTcpStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
@@ -0,0 +1,194 @@
+//! Discovery of mail servers using DNS SRV records, as described in RFC 6186 and RFC 8314.
+
+use crate::{
+    config::{
+        AuthenticationType, Config, EmailProvider, EmailProviderProperty, SecurityType, Server,
+        ServerProperty, ServerType,
+    },
+    error::{Error, ErrorKind, Result},
+    transport::SrvRecord,
+};
+
+const EMAIL_ADDRESS_PLACEHOLDER: &str = "%EMAILADDRESS%";
+
+/// The SRV service labels that can point to a mail server, in the order we prefer them.
+pub const SERVICES: [&str; 6] = [
+    "_imaps._tcp",
+    "_imap._tcp",
+    "_pop3s._tcp",
+    "_pop3._tcp",
+    "_submissions._tcp",
+    "_submission._tcp",
+];
+
+/// The kind of server and the security it uses, derived from a service label.
+fn service_info(service: &str) -> Option<(ServerType, SecurityType)> {
+    match service {
+        "_imaps._tcp" => Some((ServerType::Imap, SecurityType::Tls)),
+        "_imap._tcp" => Some((ServerType::Imap, SecurityType::Starttls)),
+        "_pop3s._tcp" => Some((ServerType::Pop3, SecurityType::Tls)),
+        "_pop3._tcp" => Some((ServerType::Pop3, SecurityType::Starttls)),
+        "_submissions._tcp" => Some((ServerType::Smtp, SecurityType::Tls)),
+        "_submission._tcp" => Some((ServerType::Smtp, SecurityType::Starttls)),
+        _ => None,
+    }
+}
+
+/// The record name that has to be queried for a given service and domain.
+pub fn record_name<S: AsRef<str>, D: AsRef<str>>(service: S, domain: D) -> String {
+    format!(
+        "{}.{}.",
+        service.as_ref(),
+        domain.as_ref().trim_end_matches('.')
+    )
+}
+
+/// Orders SRV records by priority (lowest first) and then by weight (highest first).
+///
+/// Records with a target of "." are removed, as they signal that the service is decidedly not available.
+pub fn sort(records: &mut Vec<SrvRecord>) {
+    records.retain(|srv| !srv.target().trim_end_matches('.').is_empty());
+
+    records.sort_by(|a, b| {
+        a.priority()
+            .cmp(&b.priority())
+            .then_with(|| b.weight().cmp(&a.weight()))
+    });
+}
+
+/// Creates a config from the SRV records that were found for each service, the records for a service should already be sorted by preference.
+pub fn into_config<D: AsRef<str>>(
+    domain: D,
+    services: Vec<(&str, Vec<SrvRecord>)>,
+) -> Result<Config> {
+    let mut properties = vec![EmailProviderProperty::Domain(domain.as_ref().to_string())];
+
+    let mut ttls = Vec::new();
+
+    for (service, records) in services {
+        let (server_type, security_type) = match service_info(service) {
+            Some(info) => info,
+            None => continue,
+        };
+
+        // Only the most preferred record is used, the others are merely fallbacks for the same service.
+        let record = match records.first() {
+            Some(record) => record,
+            None => continue,
+        };
+
+        let hostname = record.target();
+
+        ttls.push(record.ttl());
+
+        let server = Server::new(
+            server_type,
+            vec![
+                ServerProperty::Hostname(hostname.trim_end_matches('.').to_string()),
+                ServerProperty::Port(record.port()),
+                ServerProperty::SocketType(security_type),
+                // RFC 6186 section 4 states that the full email address should be used as the username.
+                ServerProperty::Username(EMAIL_ADDRESS_PLACEHOLDER.to_string()),
+                ServerProperty::Authentication(AuthenticationType::PasswordCleartext),
+            ],
+        );
+
+        if service.starts_with("_submission") {
+            properties.push(EmailProviderProperty::OutgoingServer(server))
+        } else {
+            properties.push(EmailProviderProperty::IncomingServer(server))
+        }
+    }
+
+    if properties.len() < 2 {
+        return Err(Error::new(
+            ErrorKind::NoRecordsFound,
+            "No SRV records were found for any mail service",
+        ));
+    }
+
+    let provider = EmailProvider::new(domain.as_ref(), properties);
+
+    let mut config = Config::new(provider);
+
+    // The config is only valid for as long as the first of its records expires.
+    for ttl in ttls {
+        config.limit_max_age(ttl);
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        config::{SecurityType, ServerType},
+        transport::SrvRecord,
+    };
+
+    #[test]
+    fn test_into_config() {
+        let services = vec![
+            (
+                "_imaps._tcp",
+                vec![SrvRecord::new(0, 1, 993, "imap.example.com.")
+                    .with_ttl(Duration::from_secs(300))],
+            ),
+            ("_pop3s._tcp", vec![]),
+            (
+                "_submission._tcp",
+                vec![SrvRecord::new(0, 1, 587, "smtp.example.com.")
+                    .with_ttl(Duration::from_secs(60))],
+            ),
+        ];
+
+        let config = super::into_config("example.com", services).unwrap();
+
+        let provider = config.email_provider();
+
+        let incoming = provider.incoming_servers();
+
+        assert_eq!(incoming.len(), 1);
+        assert_eq!(incoming[0].server_type(), &ServerType::Imap);
+        assert_eq!(incoming[0].hostname(), Some("imap.example.com"));
+        assert_eq!(incoming[0].security_type(), Some(&SecurityType::Tls));
+
+        let outgoing = provider.outgoing_servers();
+
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].port(), Some(&587));
+        assert_eq!(outgoing[0].security_type(), Some(&SecurityType::Starttls));
+
+        assert_eq!(config.max_age(), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut records = vec![
+            SrvRecord::new(10, 5, 993, "backup.example.com."),
+            SrvRecord::new(0, 1, 993, "light.example.com."),
+            SrvRecord::new(0, 10, 993, "heavy.example.com."),
+            SrvRecord::new(0, 0, 0, "."),
+        ];
+
+        super::sort(&mut records);
+
+        let targets: Vec<_> = records.iter().map(|srv| srv.target()).collect();
+
+        assert_eq!(
+            targets,
+            vec![
+                "heavy.example.com.",
+                "light.example.com.",
+                "backup.example.com."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_services() {
+        assert!(super::into_config("example.com", vec![("_imaps._tcp", vec![])]).is_err());
+    }
+}
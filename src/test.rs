@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 
-#[cfg_attr(feature = "runtime-async-std", async_std::test)]
+#[cfg_attr(
+    all(feature = "runtime-async-std", not(feature = "runtime-tokio")),
+    async_std::test
+)]
 #[cfg_attr(feature = "runtime-tokio", tokio::test)]
 pub async fn from_domain() {
     let mut addresses = HashMap::new();
@@ -0,0 +1,266 @@
+//! Traits that allow the http client and DNS resolver used during discovery to be swapped out, e.g. for a different http library or an in-process mock.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::error::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+/// An http request that has to be sent by a [`HttpTransport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    method: Method,
+    url: String,
+    content_type: Option<String>,
+    body: Option<String>,
+}
+
+impl Request {
+    pub(crate) fn get<U: Into<String>>(url: U) -> Self {
+        Self {
+            method: Method::Get,
+            url: url.into(),
+            content_type: None,
+            body: None,
+        }
+    }
+
+    pub(crate) fn post<U: Into<String>, C: Into<String>, B: Into<String>>(
+        url: U,
+        content_type: C,
+        body: B,
+    ) -> Self {
+        Self {
+            method: Method::Post,
+            url: url.into(),
+            content_type: Some(content_type.into()),
+            body: Some(body.into()),
+        }
+    }
+
+    /// The http method that should be used.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The url that should be requested.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The value of the content type header, if the request has a body.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// The body that should be sent along with the request.
+    pub fn body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+}
+
+/// An http response returned by a [`HttpTransport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+}
+
+impl Response {
+    pub fn new<B: Into<Bytes>>(status: u16, body: B) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    /// Adds a header to the response.
+    pub fn with_header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+
+        self
+    }
+
+    /// The http status code.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Whether the status code is in the 2xx range.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The value of the first header with the given name, compared case insensitively.
+    pub fn header<N: AsRef<str>>(&self, name: N) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name.as_ref()))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The response body.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    pub fn into_body(self) -> Bytes {
+        self.body
+    }
+}
+
+/// Something that can send http requests.
+#[async_trait]
+pub trait HttpTransport: Send + Sync {
+    /// Sends the request and returns the response.
+    ///
+    /// Responses with an unsuccessful status code should still be returned as a [`Response`], errors are meant for requests that could not be completed at all.
+    async fn send(&self, request: Request) -> Result<Response>;
+}
+
+/// A DNS TXT record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxtRecord {
+    data: Bytes,
+    ttl: Option<Duration>,
+}
+
+impl TxtRecord {
+    pub fn new<D: Into<Bytes>>(data: D) -> Self {
+        Self {
+            data: data.into(),
+            ttl: None,
+        }
+    }
+
+    /// Sets how long the record may be cached.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+
+        self
+    }
+
+    /// The first character string of the record.
+    pub fn data(&self) -> &Bytes {
+        &self.data
+    }
+
+    /// How long the record may be cached, if the resolver knows.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+}
+
+/// A DNS SRV record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
+    ttl: Option<Duration>,
+}
+
+impl SrvRecord {
+    pub fn new<T: Into<String>>(priority: u16, weight: u16, port: u16, target: T) -> Self {
+        Self {
+            priority,
+            weight,
+            port,
+            target: target.into(),
+            ttl: None,
+        }
+    }
+
+    /// Sets how long the record may be cached.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+
+        self
+    }
+
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    pub fn weight(&self) -> u16 {
+        self.weight
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The hostname of the server that provides the service, a target of "." means the service is not available.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// How long the record may be cached, if the resolver knows.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+}
+
+/// A DNS MX record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MxRecord {
+    preference: u16,
+    exchange: String,
+    ttl: Option<Duration>,
+}
+
+impl MxRecord {
+    pub fn new<E: Into<String>>(preference: u16, exchange: E) -> Self {
+        Self {
+            preference,
+            exchange: exchange.into(),
+            ttl: None,
+        }
+    }
+
+    /// Sets how long the record may be cached.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+
+        self
+    }
+
+    pub fn preference(&self) -> u16 {
+        self.preference
+    }
+
+    /// The hostname of the mail exchanger, an exchange of "." means the domain does not accept email.
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    /// How long the record may be cached, if the resolver knows.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+}
+
+/// Something that can resolve the DNS records needed during discovery.
+///
+/// The records may be returned in any order. A name without any records of the requested type should result in an error with [`crate::error::ErrorKind::NoRecordsFound`].
+#[async_trait]
+pub trait DnsResolver: Send + Sync {
+    /// The TXT records for a name, only the first character string of each record is needed.
+    async fn txt(&self, name: &str) -> Result<Vec<TxtRecord>>;
+
+    /// The SRV records for a name.
+    async fn srv(&self, name: &str) -> Result<Vec<SrvRecord>>;
+
+    /// The MX records for a name.
+    async fn mx(&self, name: &str) -> Result<Vec<MxRecord>>;
+}
@@ -1,10 +1,100 @@
-use regex::Regex;
+/// Escapes text so it can be used in XML element content and attribute values.
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Second level labels that are commonly used below a country code top level domain, e.g. `co.uk` or `com.au`.
+const SECOND_LEVEL_LABELS: [&str; 9] = ["co", "com", "net", "org", "gov", "edu", "ac", "ne", "or"];
+
+/// Approximates the registrable base domain of a hostname, e.g. `aspmx.l.google.com` becomes `google.com` and `mx.example.co.uk` becomes `example.co.uk`.
+///
+/// This does not use the full public suffix list, it only knows about the most common second level domains below country code top level domains.
+pub fn base_domain(hostname: &str) -> &str {
+    let hostname = hostname.trim_end_matches('.');
+
+    let labels: Vec<&str> = hostname.split('.').collect();
+
+    let label_count = match labels.as_slice() {
+        [.., second_level, top_level]
+            if top_level.len() == 2
+                && SECOND_LEVEL_LABELS.contains(&second_level.to_ascii_lowercase().as_str()) =>
+        {
+            3
+        }
+        _ => 2,
+    };
+
+    if labels.len() <= label_count {
+        return hostname;
+    }
+
+    let skip: usize = labels[..labels.len() - label_count]
+        .iter()
+        .map(|label| label.len() + 1)
+        .sum();
+
+    &hostname[skip..]
+}
+
+/// Whether a hostname is the given domain or one of its subdomains, compared case insensitively.
+pub fn is_within_domain(hostname: &str, domain: &str) -> bool {
+    let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+
+    hostname == domain || hostname.ends_with(&format!(".{}", domain))
+}
+
+/// Whether a domain listed by a provider matches a domain, a listed wildcard like `*.example.com` matches every subdomain of `example.com` but not `example.com` itself.
+pub fn matches_domain(listed: &str, domain: &str) -> bool {
+    match listed.strip_prefix("*.") {
+        Some(parent) => {
+            is_within_domain(domain, parent)
+                && !domain
+                    .trim_end_matches('.')
+                    .eq_ignore_ascii_case(parent.trim_end_matches('.'))
+        }
+        None => listed
+            .trim_end_matches('.')
+            .eq_ignore_ascii_case(domain.trim_end_matches('.')),
+    }
+}
 
-const EMAIL_REGEX: &str =
-    r"^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})";
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_is_within_domain() {
+        assert!(super::is_within_domain("imap.Example.com.", "example.com"));
+        assert!(super::is_within_domain("example.com", "example.com"));
+        assert!(!super::is_within_domain("badexample.com", "example.com"));
+    }
 
-pub fn validate_email(unknown_str: &str) -> bool {
-    let email_regex = Regex::new(EMAIL_REGEX).unwrap();
+    #[test]
+    fn test_matches_domain() {
+        assert!(super::matches_domain("Example.com", "example.com."));
+        assert!(super::matches_domain(
+            "*.example.com",
+            "mail.eu.example.com"
+        ));
+        assert!(!super::matches_domain("*.example.com", "example.com"));
+        assert!(!super::matches_domain("example.com", "mail.example.com"));
+    }
 
-    email_regex.is_match(unknown_str)
+    #[test]
+    fn test_base_domain() {
+        assert_eq!(super::base_domain("aspmx.l.google.com."), "google.com");
+        assert_eq!(
+            super::base_domain("in1-smtp.messagingengine.com"),
+            "messagingengine.com"
+        );
+        assert_eq!(
+            super::base_domain("example-com.mail.protection.outlook.com"),
+            "outlook.com"
+        );
+        assert_eq!(super::base_domain("mx.example.co.uk"), "example.co.uk");
+        assert_eq!(super::base_domain("example.com"), "example.com");
+    }
 }
@@ -0,0 +1,202 @@
+//! Consistency checks on configs, as a config that was found is not necessarily one that makes sense for the requested domain.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{AuthenticationType, Config, SecurityType, ServerType},
+    utils,
+};
+
+/// A warning about a config, the config can still be used but should be looked at with suspicion.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Diagnostic {
+    /// The domains the provider lists do not include the requested domain.
+    DomainNotListed(String),
+    /// A server is hosted under a domain that is not related to the requested domain or the provider, contains the hostname.
+    UnrelatedHostname(String),
+    /// A server accepts a cleartext password over an unencrypted connection, contains the hostname.
+    PlaintextPassword(String),
+    /// A server does not specify which port to connect to, contains the hostname.
+    MissingPort(String),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::DomainNotListed(domain) => {
+                write!(f, "The provider does not list the domain {}", domain)
+            }
+            Diagnostic::UnrelatedHostname(hostname) => write!(
+                f,
+                "The server {} is not hosted under the domain or the provider",
+                hostname
+            ),
+            Diagnostic::PlaintextPassword(hostname) => write!(
+                f,
+                "The server {} sends passwords in cleartext over an unencrypted connection",
+                hostname
+            ),
+            Diagnostic::MissingPort(hostname) => {
+                write!(f, "The server {} does not specify a port", hostname)
+            }
+        }
+    }
+}
+
+/// Checks whether a config is consistent with the domain it was requested for.
+///
+/// Servers are only related to the domain if they are hosted under it, see [`validate_with_mx`] to also accept the domain's mail exchangers.
+pub fn validate<D: AsRef<str>>(config: &Config, domain: D) -> Vec<Diagnostic> {
+    validate_with_mx::<D, &str>(config, domain, &[])
+}
+
+/// Checks whether a config is consistent with the domain it was requested for, where servers hosted under the base domain of one of the domain's mail exchangers are related to it as well.
+pub fn validate_with_mx<D: AsRef<str>, M: AsRef<str>>(
+    config: &Config,
+    domain: D,
+    exchanges: &[M],
+) -> Vec<Diagnostic> {
+    let domain = domain.as_ref();
+    let provider = config.email_provider();
+
+    let mut diagnostics = Vec::new();
+
+    if !provider
+        .domain()
+        .iter()
+        .any(|listed| utils::matches_domain(listed, domain))
+    {
+        diagnostics.push(Diagnostic::DomainNotListed(domain.to_string()));
+    }
+
+    // The domains and id the config lists itself are not used, as any config could claim to belong to the domain.
+    let mut related = vec![domain];
+
+    related.extend(
+        exchanges
+            .iter()
+            .map(|exchange| exchange.as_ref().trim_end_matches('.'))
+            .filter(|exchange| !exchange.is_empty())
+            .map(utils::base_domain),
+    );
+
+    for server in provider.servers() {
+        let hostname = match server.hostname() {
+            Some(hostname) => hostname.replace("%EMAILDOMAIN%", domain),
+            None => continue,
+        };
+
+        if !related
+            .iter()
+            .any(|related| utils::is_within_domain(&hostname, related))
+        {
+            diagnostics.push(Diagnostic::UnrelatedHostname(hostname.clone()));
+        }
+
+        if server.server_type() == &ServerType::Exchange {
+            continue;
+        }
+
+        let unencrypted = matches!(server.security_type(), None | Some(SecurityType::Plain));
+
+        if unencrypted
+            && server
+                .authentication_type()
+                .contains(&&AuthenticationType::PasswordCleartext)
+        {
+            diagnostics.push(Diagnostic::PlaintextPassword(hostname.clone()));
+        }
+
+        if server.port().is_none() {
+            diagnostics.push(Diagnostic::MissingPort(hostname));
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{AuthenticationType, Config, SecurityType, Server};
+
+    use super::Diagnostic;
+
+    #[test]
+    fn test_validate() {
+        let config = Config::builder()
+            .provider("example.com")
+            .domain("example.com")
+            .incoming(
+                Server::imap("imap.example.com", 143, SecurityType::Plain)
+                    .with_authentication(AuthenticationType::PasswordCleartext),
+            )
+            .outgoing(Server::smtp("smtp.example.org", 465, SecurityType::Tls))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            super::validate(&config, "mail.example.com"),
+            vec![
+                Diagnostic::DomainNotListed("mail.example.com".to_string()),
+                Diagnostic::UnrelatedHostname("imap.example.com".to_string()),
+                Diagnostic::PlaintextPassword("imap.example.com".to_string()),
+                Diagnostic::UnrelatedHostname("smtp.example.org".to_string()),
+            ]
+        );
+
+        assert_eq!(super::validate(&config, "example.com").len(), 2);
+
+        assert_eq!(
+            super::validate_with_mx(&config, "example.com", &["mx1.example.org."]),
+            vec![Diagnostic::PlaintextPassword(
+                "imap.example.com".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_config_does_not_vouch_for_itself() {
+        // A config that lists the domain does not make the servers of its own provider related to it.
+        let config = Config::builder()
+            .provider("attacker.net")
+            .domain("example.com")
+            .domain("attacker.net")
+            .incoming(Server::imap("imap.attacker.net", 993, SecurityType::Tls))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            super::validate(&config, "example.com"),
+            vec![Diagnostic::UnrelatedHostname(
+                "imap.attacker.net".to_string()
+            )]
+        );
+        assert!(super::validate_with_mx(&config, "example.com", &["mx.attacker.net"]).is_empty());
+    }
+
+    #[test]
+    fn test_missing_port() {
+        let config = Config::from_xml(
+            r#"
+            <clientConfig version="1.1">
+                <emailProvider id="example.com">
+                    <domain>example.com</domain>
+                    <incomingServer type="imap">
+                        <hostname>imap.example.com</hostname>
+                        <socketType>SSL</socketType>
+                    </incomingServer>
+                </emailProvider>
+            </clientConfig>
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            super::validate(&config, "example.com"),
+            vec![Diagnostic::MissingPort("imap.example.com".to_string())]
+        );
+    }
+}
@@ -0,0 +1,989 @@
+//! Verifies that the servers in a config are actually reachable, by connecting to them and performing the TLS handshake and greeting of their protocol.
+//!
+//! While connected, the capabilities of each server are read so the authentication types a config declares can be checked against what the server offers.
+
+use std::{
+    fmt,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use async_net::TcpStream;
+use futures::{
+    future::{join_all, select, Either},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+};
+use futures_rustls::TlsConnector;
+use futures_timer::Delay;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, ProtocolVersion, RootCertStore, ServerName,
+};
+
+use crate::{
+    config::{AuthenticationType, Config, SecurityType, Server, ServerType},
+    error::{Error, ErrorKind, Result},
+};
+
+/// The name we introduce ourselves with when greeting an SMTP server.
+const EHLO_NAME: &str = "localhost";
+
+/// The TLS version that was negotiated with a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsVersion::Tls12 => write!(f, "TLSv1.2"),
+            TlsVersion::Tls13 => write!(f, "TLSv1.3"),
+        }
+    }
+}
+
+/// The outcome of verifying a single server.
+#[derive(Debug)]
+pub struct Report {
+    pub(crate) hostname: String,
+    pub(crate) port: u16,
+    pub(crate) server_type: ServerType,
+    pub(crate) security_type: SecurityType,
+    pub(crate) reachable: bool,
+    pub(crate) latency: Option<Duration>,
+    pub(crate) tls_version: Option<TlsVersion>,
+    pub(crate) certificate_valid: Option<bool>,
+    pub(crate) greeting: Vec<String>,
+    pub(crate) capabilities: Option<Vec<String>>,
+    pub(crate) declared: Vec<AuthenticationType>,
+    pub(crate) error: Option<Error>,
+}
+
+impl Report {
+    pub(crate) fn new(
+        hostname: &str,
+        port: u16,
+        server_type: ServerType,
+        security_type: SecurityType,
+    ) -> Self {
+        Self {
+            hostname: hostname.to_string(),
+            port,
+            server_type,
+            security_type,
+            reachable: false,
+            latency: None,
+            tls_version: None,
+            certificate_valid: None,
+            greeting: Vec::new(),
+            capabilities: None,
+            declared: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// The hostname that was connected to.
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// The port that was connected to, if the server did not specify one the default port for its protocol is used.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn server_type(&self) -> &ServerType {
+        &self.server_type
+    }
+
+    pub fn security_type(&self) -> &SecurityType {
+        &self.security_type
+    }
+
+    /// Whether the server accepted the connection and answered with a valid greeting.
+    pub fn is_reachable(&self) -> bool {
+        self.reachable
+    }
+
+    /// How long it took to establish the TCP connection.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// The TLS version that was negotiated, if the connection is encrypted.
+    pub fn tls_version(&self) -> Option<&TlsVersion> {
+        self.tls_version.as_ref()
+    }
+
+    /// Whether the certificate is trusted and valid for the hostname, if the connection is encrypted.
+    pub fn certificate_valid(&self) -> Option<bool> {
+        self.certificate_valid
+    }
+
+    /// The greeting of an IMAP or POP3 server, or the EHLO response of an SMTP server.
+    pub fn greeting(&self) -> &[String] {
+        &self.greeting
+    }
+
+    /// The capabilities advertised by the server, if they could be read.
+    ///
+    /// These come from the IMAP `CAPABILITY` response, the POP3 `CAPA` response or the SMTP `EHLO` response.
+    pub fn capabilities(&self) -> Option<&[String]> {
+        self.capabilities.as_deref()
+    }
+
+    /// The SASL mechanisms the server offers, e.g. `PLAIN` or `XOAUTH2`.
+    pub fn mechanisms(&self) -> Vec<&str> {
+        let capabilities = match &self.capabilities {
+            Some(capabilities) => capabilities,
+            None => return Vec::new(),
+        };
+
+        let mut mechanisms = Vec::new();
+
+        for capability in capabilities {
+            let mut words = capability.split([' ', '=']);
+
+            let keyword = words.next().unwrap_or_default();
+
+            let is_sasl = match self.server_type {
+                ServerType::Imap => keyword.eq_ignore_ascii_case("AUTH"),
+                ServerType::Pop3 => keyword.eq_ignore_ascii_case("SASL"),
+                ServerType::Smtp => keyword.eq_ignore_ascii_case("AUTH"),
+                ServerType::Exchange => false,
+            };
+
+            if is_sasl {
+                for mechanism in words.filter(|word| !word.is_empty()) {
+                    if !mechanisms
+                        .iter()
+                        .any(|known: &&str| known.eq_ignore_ascii_case(mechanism))
+                    {
+                        mechanisms.push(mechanism);
+                    }
+                }
+            }
+        }
+
+        mechanisms
+    }
+
+    /// The authentication types the server offers, derived from its SASL mechanisms and protocol specific capabilities.
+    pub fn authentication_types(&self) -> Vec<AuthenticationType> {
+        let mut types = Vec::new();
+
+        let capabilities = match &self.capabilities {
+            Some(capabilities) => capabilities,
+            None => return types,
+        };
+
+        let has = |name: &str| {
+            capabilities
+                .iter()
+                .any(|capability| capability.eq_ignore_ascii_case(name))
+        };
+
+        // Besides SASL, IMAP has a LOGIN command and POP3 has USER/PASS to send a password in the clear.
+        let plain_login = match self.server_type {
+            ServerType::Imap => !has("LOGINDISABLED"),
+            ServerType::Pop3 => has("USER"),
+            _ => false,
+        };
+
+        if plain_login {
+            types.push(AuthenticationType::PasswordCleartext);
+        }
+
+        for mechanism in self.mechanisms() {
+            if let Some(authentication_type) = authentication_type(mechanism) {
+                if !types.contains(&authentication_type) {
+                    types.push(authentication_type);
+                }
+            }
+        }
+
+        types
+    }
+
+    /// The authentication types the config declares for this server, but the server does not offer.
+    ///
+    /// This is always empty if the capabilities of the server could not be read. Types that are not negotiated using SASL, like `client-IP-address` or `TLS-client-cert`, are never reported.
+    pub fn unsupported_authentication(&self) -> Vec<&AuthenticationType> {
+        if self.capabilities.is_none() {
+            return Vec::new();
+        }
+
+        let offered = self.authentication_types();
+
+        self.declared
+            .iter()
+            .filter(|declared| is_negotiated(declared) && !offered.contains(declared))
+            .collect()
+    }
+
+    /// Why the server could not be verified.
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+}
+
+/// Accepts any certificate, used to still report on servers whose certificate is not valid.
+struct AcceptAnyCertificate;
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Connects to the servers in a config to check whether they work.
+#[derive(Debug, Clone)]
+pub struct Verifier {
+    timeout: Duration,
+    roots: RootCertStore,
+}
+
+impl Default for Verifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Verifier {
+    pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Creates a verifier that trusts the Mozilla root certificates.
+    pub fn new() -> Self {
+        let mut roots = RootCertStore::empty();
+
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+
+        Self {
+            timeout: Self::TIMEOUT,
+            roots,
+        }
+    }
+
+    /// The maximum amount of time verifying a single server may take.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+
+        self
+    }
+
+    /// Trusts an additional (DER encoded) root certificate, e.g. for a private certificate authority.
+    pub fn add_root_certificate<C: AsRef<[u8]>>(mut self, certificate: C) -> Result<Self> {
+        self.roots
+            .add(&Certificate(certificate.as_ref().to_vec()))
+            .map_err(|err| {
+                Error::new(
+                    ErrorKind::BadInput,
+                    format!("Invalid root certificate: {}", err),
+                )
+            })?;
+
+        Ok(self)
+    }
+
+    fn tls_config(&self, verify: bool) -> Arc<ClientConfig> {
+        let builder = ClientConfig::builder().with_safe_defaults();
+
+        let config = if verify {
+            builder
+                .with_root_certificates(self.roots.clone())
+                .with_no_client_auth()
+        } else {
+            builder
+                .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+                .with_no_client_auth()
+        };
+
+        Arc::new(config)
+    }
+
+    /// Verifies every IMAP, POP3 and SMTP server in the config concurrently.
+    ///
+    /// Exchange servers are skipped, as they are not reached using a mail protocol.
+    pub async fn verify_config(&self, config: &Config) -> Vec<Report> {
+        let servers = config
+            .email_provider()
+            .servers()
+            .into_iter()
+            .filter(|server| server.server_type() != &ServerType::Exchange);
+
+        join_all(servers.map(|server| self.verify_server(server))).await
+    }
+
+    /// Connects to a server using the security type it specifies and reads its greeting.
+    ///
+    /// If the certificate of the server is not valid, this is reported and the connection is retried without verifying the certificate, so the rest of the report can still be filled in.
+    pub async fn verify_server(&self, server: &Server) -> Report {
+        let security_type = server
+            .security_type()
+            .cloned()
+            .unwrap_or(SecurityType::Plain);
+
+        let server_type = server.server_type().clone();
+
+        let port = server
+            .port()
+            .copied()
+            .or_else(|| default_port(&server_type, &security_type))
+            .unwrap_or_default();
+
+        let hostname = server.hostname().unwrap_or_default();
+
+        let mut report = Report::new(hostname, port, server_type, security_type);
+
+        report.declared = server.authentication_type().into_iter().cloned().collect();
+
+        if hostname.is_empty() || port == 0 {
+            report.error = Some(Error::new(
+                ErrorKind::BadInput,
+                "Server does not specify a hostname and port that can be connected to",
+            ));
+
+            return report;
+        }
+
+        let mut result = self.connect(&report, self.tls_config(true)).await;
+
+        if let Err(err) = &result {
+            if is_certificate_error(err) {
+                report.certificate_valid = Some(false);
+
+                result = self.connect(&report, self.tls_config(false)).await;
+            }
+        }
+
+        match result {
+            Ok(session) => {
+                report.reachable = true;
+                report.latency = Some(session.latency);
+                report.tls_version = session.tls_version;
+                report.greeting = session.greeting;
+                report.capabilities = session.capabilities;
+
+                if report.tls_version.is_some() && report.certificate_valid.is_none() {
+                    report.certificate_valid = Some(true);
+                }
+            }
+            Err(err) => report.error = Some(err),
+        }
+
+        report
+    }
+
+    async fn connect(&self, report: &Report, tls_config: Arc<ClientConfig>) -> Result<Outcome> {
+        timeout(
+            self.timeout,
+            connect(
+                &report.hostname,
+                report.port,
+                &report.server_type,
+                &report.security_type,
+                tls_config,
+            ),
+        )
+        .await
+    }
+}
+
+/// Verifies every IMAP, POP3 and SMTP server in the config using the default [`Verifier`].
+pub async fn verify(config: &Config) -> Vec<Report> {
+    Verifier::new().verify_config(config).await
+}
+
+/// The port that is used by a protocol when a server does not specify one.
+fn default_port(server_type: &ServerType, security_type: &SecurityType) -> Option<u16> {
+    match (server_type, security_type) {
+        (ServerType::Imap, SecurityType::Tls) => Some(993),
+        (ServerType::Imap, _) => Some(143),
+        (ServerType::Pop3, SecurityType::Tls) => Some(995),
+        (ServerType::Pop3, _) => Some(110),
+        (ServerType::Smtp, SecurityType::Tls) => Some(465),
+        (ServerType::Smtp, _) => Some(587),
+        (ServerType::Exchange, _) => None,
+    }
+}
+
+/// Maps a SASL mechanism onto the authentication type Thunderbird uses for it.
+pub fn authentication_type(mechanism: &str) -> Option<AuthenticationType> {
+    match mechanism.to_ascii_uppercase().as_str() {
+        "PLAIN" | "LOGIN" => Some(AuthenticationType::PasswordCleartext),
+        "CRAM-MD5" | "DIGEST-MD5" => Some(AuthenticationType::PasswordEncrypted),
+        "XOAUTH2" | "OAUTHBEARER" => Some(AuthenticationType::OAuth2),
+        "GSSAPI" => Some(AuthenticationType::GsApi),
+        "NTLM" => Some(AuthenticationType::Ntlm),
+        _ => None,
+    }
+}
+
+/// Whether an authentication type is something a server has to advertise as a capability.
+fn is_negotiated(authentication_type: &AuthenticationType) -> bool {
+    matches!(
+        authentication_type,
+        AuthenticationType::PasswordCleartext
+            | AuthenticationType::PasswordEncrypted
+            | AuthenticationType::OAuth2
+            | AuthenticationType::GsApi
+            | AuthenticationType::Ntlm
+    )
+}
+
+fn is_certificate_error(error: &Error) -> bool {
+    match error.kind() {
+        ErrorKind::Io(err) => matches!(
+            err.get_ref()
+                .and_then(|inner| inner.downcast_ref::<rustls::Error>()),
+            Some(rustls::Error::InvalidCertificate(_))
+        ),
+        _ => false,
+    }
+}
+
+async fn timeout<T, F: Future<Output = Result<T>>>(duration: Duration, future: F) -> Result<T> {
+    futures::pin_mut!(future);
+
+    match select(future, Delay::new(duration)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(Error::new(
+            ErrorKind::Timeout,
+            "Server did not respond in time",
+        )),
+    }
+}
+
+/// What we learned from a successful connection.
+struct Outcome {
+    latency: Duration,
+    tls_version: Option<TlsVersion>,
+    greeting: Vec<String>,
+    capabilities: Option<Vec<String>>,
+}
+
+async fn connect(
+    hostname: &str,
+    port: u16,
+    server_type: &ServerType,
+    security_type: &SecurityType,
+    tls_config: Arc<ClientConfig>,
+) -> Result<Outcome> {
+    let start = Instant::now();
+
+    let stream = TcpStream::connect((hostname, port)).await?;
+
+    let latency = start.elapsed();
+
+    match security_type {
+        SecurityType::Plain => {
+            let mut session = Session::new(stream);
+
+            let greeting = session.greet(server_type).await?;
+            let capabilities = session.capabilities(server_type, &greeting).await;
+
+            Ok(Outcome {
+                latency,
+                tls_version: None,
+                greeting,
+                capabilities,
+            })
+        }
+        SecurityType::Tls => {
+            let (stream, tls_version) = handshake(hostname, stream, tls_config).await?;
+
+            let mut session = Session::new(stream);
+
+            let greeting = session.greet(server_type).await?;
+            let capabilities = session.capabilities(server_type, &greeting).await;
+
+            Ok(Outcome {
+                latency,
+                tls_version,
+                greeting,
+                capabilities,
+            })
+        }
+        SecurityType::Starttls => {
+            let mut session = Session::new(stream);
+
+            session.greet(server_type).await?;
+            session.starttls(server_type).await?;
+
+            let (stream, tls_version) =
+                handshake(hostname, session.into_inner(), tls_config).await?;
+
+            let mut session = Session::new(stream);
+
+            // The greeting is not repeated after STARTTLS, only SMTP requires us to introduce ourselves again.
+            let greeting = match server_type {
+                ServerType::Smtp => session.ehlo().await?,
+                _ => Vec::new(),
+            };
+
+            // Capabilities have to be requested again, as servers usually only offer authentication once the connection is encrypted.
+            let capabilities = session.capabilities(server_type, &greeting).await;
+
+            Ok(Outcome {
+                latency,
+                tls_version,
+                greeting,
+                capabilities,
+            })
+        }
+    }
+}
+
+async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    hostname: &str,
+    stream: S,
+    tls_config: Arc<ClientConfig>,
+) -> Result<(futures_rustls::client::TlsStream<S>, Option<TlsVersion>)> {
+    let server_name = ServerName::try_from(hostname).map_err(|_| {
+        Error::new(
+            ErrorKind::BadInput,
+            format!("Invalid hostname: {}", hostname),
+        )
+    })?;
+
+    let stream = TlsConnector::from(tls_config)
+        .connect(server_name, stream)
+        .await?;
+
+    let tls_version = match stream.get_ref().1.protocol_version() {
+        Some(ProtocolVersion::TLSv1_2) => Some(TlsVersion::Tls12),
+        Some(ProtocolVersion::TLSv1_3) => Some(TlsVersion::Tls13),
+        _ => None,
+    };
+
+    Ok((stream, tls_version))
+}
+
+/// A line based conversation with an IMAP, POP3 or SMTP server.
+struct Session<S> {
+    reader: BufReader<S>,
+    tag: usize,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Session<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            tag: 0,
+        }
+    }
+
+    fn into_inner(self) -> S {
+        self.reader.into_inner()
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidResponse,
+                "Server closed the connection",
+            ));
+        }
+
+        Ok(line.trim_end().to_string())
+    }
+
+    async fn write_line(&mut self, line: &str) -> Result<()> {
+        let stream = self.reader.get_mut();
+
+        stream.write_all(format!("{}\r\n", line).as_bytes()).await?;
+        stream.flush().await?;
+
+        Ok(())
+    }
+
+    /// Reads a (possibly multiline) SMTP reply and checks that it has the expected status code.
+    async fn smtp_reply(&mut self, code: &str) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+
+        loop {
+            let line = self.read_line().await?;
+
+            let last = line.len() <= 3 || line.as_bytes()[3] == b' ';
+
+            if !line.starts_with(code) {
+                return Err(unexpected_response(&line));
+            }
+
+            lines.push(line);
+
+            if last {
+                return Ok(lines);
+            }
+        }
+    }
+
+    /// Sends a tagged IMAP command and waits for its completion result.
+    async fn imap_command(&mut self, command: &str) -> Result<Vec<String>> {
+        self.tag += 1;
+
+        let tag = format!("a{:03}", self.tag);
+
+        self.write_line(&format!("{} {}", tag, command)).await?;
+
+        let mut lines = Vec::new();
+
+        loop {
+            let line = self.read_line().await?;
+
+            if let Some(status) = line.strip_prefix(&tag) {
+                if !status.trim_start().starts_with("OK") {
+                    return Err(unexpected_response(&line));
+                }
+
+                lines.push(line);
+
+                return Ok(lines);
+            }
+
+            lines.push(line);
+        }
+    }
+
+    async fn pop3_command(&mut self, command: &str) -> Result<String> {
+        self.write_line(command).await?;
+
+        let line = self.read_line().await?;
+
+        if !line.starts_with("+OK") {
+            return Err(unexpected_response(&line));
+        }
+
+        Ok(line)
+    }
+
+    async fn ehlo(&mut self) -> Result<Vec<String>> {
+        self.write_line(&format!("EHLO {}", EHLO_NAME)).await?;
+
+        self.smtp_reply("250").await
+    }
+
+    /// Reads the greeting of the server, SMTP servers are also sent an EHLO as that is what their capabilities are read from.
+    async fn greet(&mut self, server_type: &ServerType) -> Result<Vec<String>> {
+        match server_type {
+            ServerType::Imap => {
+                let line = self.read_line().await?;
+
+                if !(line.starts_with("* OK") || line.starts_with("* PREAUTH")) {
+                    return Err(unexpected_response(&line));
+                }
+
+                Ok(vec![line])
+            }
+            ServerType::Pop3 => {
+                let line = self.read_line().await?;
+
+                if !line.starts_with("+OK") {
+                    return Err(unexpected_response(&line));
+                }
+
+                Ok(vec![line])
+            }
+            ServerType::Smtp => {
+                self.smtp_reply("220").await?;
+
+                self.ehlo().await
+            }
+            ServerType::Exchange => Err(Error::new(
+                ErrorKind::BadInput,
+                "Exchange servers can not be verified",
+            )),
+        }
+    }
+
+    /// Reads the capabilities of the server, SMTP servers already sent them in their EHLO response.
+    ///
+    /// Servers that do not support listing their capabilities result in `None`.
+    async fn capabilities(
+        &mut self,
+        server_type: &ServerType,
+        greeting: &[String],
+    ) -> Option<Vec<String>> {
+        match server_type {
+            ServerType::Imap => {
+                let lines = self.imap_command("CAPABILITY").await.ok()?;
+
+                let line = lines
+                    .iter()
+                    .find_map(|line| line.strip_prefix("* CAPABILITY "))?;
+
+                Some(line.split_whitespace().map(String::from).collect())
+            }
+            ServerType::Pop3 => {
+                self.pop3_command("CAPA").await.ok()?;
+
+                let mut capabilities = Vec::new();
+
+                loop {
+                    let line = self.read_line().await.ok()?;
+
+                    if line == "." {
+                        return Some(capabilities);
+                    }
+
+                    capabilities.push(line);
+                }
+            }
+            ServerType::Smtp => Some(
+                greeting
+                    .iter()
+                    // The first line only contains the name of the server.
+                    .skip(1)
+                    .map(|line| line.get(4..).unwrap_or_default().to_string())
+                    .collect(),
+            ),
+            ServerType::Exchange => None,
+        }
+    }
+
+    /// Asks the server to upgrade the connection to TLS.
+    async fn starttls(&mut self, server_type: &ServerType) -> Result<()> {
+        match server_type {
+            ServerType::Imap => {
+                self.imap_command("STARTTLS").await?;
+            }
+            ServerType::Pop3 => {
+                self.pop3_command("STLS").await?;
+            }
+            ServerType::Smtp => {
+                self.write_line("STARTTLS").await?;
+
+                self.smtp_reply("220").await?;
+            }
+            ServerType::Exchange => {
+                return Err(Error::new(
+                    ErrorKind::BadInput,
+                    "Exchange servers can not be verified",
+                ))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn unexpected_response(line: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidResponse,
+        format!("Unexpected response from server: {}", line),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::Arc,
+        thread,
+    };
+
+    use futures::executor::block_on;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+    use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+    use crate::config::{AuthenticationType, SecurityType, Server, ServerType};
+
+    use super::{Report, TlsVersion, Verifier};
+
+    /// A certificate authority and a certificate for localhost signed by it.
+    fn certificates() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let mut params = CertificateParams::new(Vec::new());
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+
+        let ca = Certificate::from_params(params).unwrap();
+        let leaf = Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()]))
+            .unwrap();
+
+        (
+            ca.serialize_der().unwrap(),
+            leaf.serialize_der_with_signer(&ca).unwrap(),
+            leaf.serialize_private_key_der(),
+        )
+    }
+
+    fn server_config(certificate: Vec<u8>, key: Vec<u8>) -> Arc<ServerConfig> {
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(certificate)],
+                rustls::PrivateKey(key),
+            )
+            .unwrap();
+
+        Arc::new(config)
+    }
+
+    /// Runs a minimal SMTP server that supports STARTTLS, for the given number of connections.
+    fn smtp_server(config: Arc<ServerConfig>, connections: usize) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+
+                stream.write_all(b"220 localhost ESMTP\r\n").unwrap();
+
+                reader.read_line(&mut line).unwrap();
+                stream
+                    .write_all(b"250-localhost\r\n250 STARTTLS\r\n")
+                    .unwrap();
+
+                reader.read_line(&mut line).unwrap();
+                stream.write_all(b"220 Ready to start TLS\r\n").unwrap();
+
+                let connection = ServerConnection::new(config.clone()).unwrap();
+                let mut tls = StreamOwned::new(connection, stream);
+                let mut reader = BufReader::new(&mut tls);
+
+                if reader.read_line(&mut line).is_err() {
+                    continue;
+                }
+
+                tls.write_all(b"250-localhost\r\n250 AUTH PLAIN LOGIN\r\n")
+                    .unwrap();
+                tls.flush().unwrap();
+            }
+        });
+
+        port
+    }
+
+    #[test]
+    fn test_starttls() {
+        let (ca, certificate, key) = certificates();
+
+        let port = smtp_server(server_config(certificate, key), 1);
+
+        let verifier = Verifier::new().add_root_certificate(ca).unwrap();
+        let server = Server::smtp("localhost", port, SecurityType::Starttls);
+
+        let report = block_on(verifier.verify_server(&server));
+
+        assert!(report.is_reachable(), "{:?}", report.error());
+        assert_eq!(report.tls_version(), Some(&TlsVersion::Tls13));
+        assert_eq!(report.certificate_valid(), Some(true));
+        assert_eq!(report.greeting().last().unwrap(), "250 AUTH PLAIN LOGIN");
+        assert_eq!(report.mechanisms(), ["PLAIN", "LOGIN"]);
+        assert!(report.latency().is_some());
+    }
+
+    #[test]
+    fn test_untrusted_certificate() {
+        let (_, certificate, key) = certificates();
+
+        // The first connection fails the certificate check, the second one ignores it.
+        let port = smtp_server(server_config(certificate, key), 2);
+
+        let server = Server::smtp("localhost", port, SecurityType::Starttls);
+
+        let report = block_on(Verifier::new().verify_server(&server));
+
+        assert!(report.is_reachable(), "{:?}", report.error());
+        assert_eq!(report.certificate_valid(), Some(false));
+        assert!(report.tls_version().is_some());
+    }
+
+    #[test]
+    fn test_plain_imap() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+
+            stream.write_all(b"* OK IMAP4rev1 ready\r\n").unwrap();
+
+            reader.read_line(&mut line).unwrap();
+            stream
+                .write_all(b"* CAPABILITY IMAP4rev1 AUTH=XOAUTH2 LOGINDISABLED\r\na001 OK\r\n")
+                .unwrap();
+        });
+
+        let server = Server::imap("localhost", port, SecurityType::Plain);
+
+        let report = block_on(Verifier::new().verify_server(&server));
+
+        assert!(report.is_reachable());
+        assert_eq!(report.tls_version(), None);
+        assert_eq!(report.certificate_valid(), None);
+        assert_eq!(report.greeting(), ["* OK IMAP4rev1 ready"]);
+        assert_eq!(report.mechanisms(), ["XOAUTH2"]);
+        assert_eq!(
+            report.authentication_types(),
+            vec![AuthenticationType::OAuth2]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_authentication() {
+        let mut report = Report::new("localhost", 587, ServerType::Smtp, SecurityType::Starttls);
+
+        report.declared = vec![
+            AuthenticationType::OAuth2,
+            AuthenticationType::GsApi,
+            AuthenticationType::ClientIPAddress,
+        ];
+
+        assert!(report.unsupported_authentication().is_empty());
+
+        report.capabilities = Some(vec![
+            "PIPELINING".to_string(),
+            "AUTH PLAIN LOGIN XOAUTH2".to_string(),
+        ]);
+
+        assert_eq!(report.mechanisms(), ["PLAIN", "LOGIN", "XOAUTH2"]);
+        assert_eq!(
+            report.unsupported_authentication(),
+            vec![&AuthenticationType::GsApi]
+        );
+    }
+
+    #[test]
+    fn test_unreachable() {
+        // Bind and immediately drop a listener to get a port nothing is listening on.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let server = Server::imap("127.0.0.1", port, SecurityType::Tls);
+
+        let report = block_on(Verifier::new().verify_server(&server));
+
+        assert!(!report.is_reachable());
+        assert!(report.error().is_some());
+    }
+}
@@ -0,0 +1,439 @@
+use std::fmt::Write;
+
+use crate::{
+    config::{
+        AuthenticationType, Config, Documentation, EmailProviderProperty, OAuth2Config, Pop3Config,
+        SecurityType, Server, ServerProperty, ServerType,
+    },
+    utils::escape_xml,
+};
+
+const INDENT: &str = "    ";
+
+fn security_type(security_type: &SecurityType) -> &'static str {
+    match security_type {
+        SecurityType::Plain => "plain",
+        SecurityType::Starttls => "STARTTLS",
+        SecurityType::Tls => "SSL",
+    }
+}
+
+fn server_type(server_type: &ServerType) -> &'static str {
+    match server_type {
+        ServerType::Exchange => "exchange",
+        ServerType::Imap => "imap",
+        ServerType::Pop3 => "pop3",
+        ServerType::Smtp => "smtp",
+    }
+}
+
+fn authentication_type(authentication_type: &AuthenticationType) -> &'static str {
+    match authentication_type {
+        AuthenticationType::PasswordCleartext => "password-cleartext",
+        AuthenticationType::PasswordEncrypted => "password-encrypted",
+        AuthenticationType::Ntlm => "NTLM",
+        AuthenticationType::GsApi => "GSAPI",
+        AuthenticationType::ClientIPAddress => "client-IP-address",
+        AuthenticationType::TlsClientCert => "TLS-client-cert",
+        AuthenticationType::OAuth2 => "OAuth2",
+        AuthenticationType::None => "None",
+    }
+}
+
+/// Writes a single element containing only text.
+fn element(xml: &mut String, depth: usize, name: &str, text: &str) {
+    writeln!(
+        xml,
+        "{}<{}>{}</{}>",
+        INDENT.repeat(depth),
+        name,
+        escape_xml(text),
+        name
+    )
+    .unwrap();
+}
+
+fn pop3(xml: &mut String, depth: usize, pop3: &Pop3Config) {
+    let indent = INDENT.repeat(depth);
+
+    writeln!(xml, "{}<pop3>", indent).unwrap();
+
+    element(
+        xml,
+        depth + 1,
+        "leaveMessagesOnServer",
+        &pop3.leave_messages_on_server().to_string(),
+    );
+
+    if let Some(download_on_biff) = pop3.download_on_biff() {
+        element(
+            xml,
+            depth + 1,
+            "downloadOnBiff",
+            &download_on_biff.to_string(),
+        );
+    }
+
+    if let Some(time) = pop3.time_to_leave_messages_on_server() {
+        element(
+            xml,
+            depth + 1,
+            "daysToLeaveMessagesOnServer",
+            &(time.as_secs() / (24 * 60 * 60)).to_string(),
+        );
+    }
+
+    if let Some(interval) = pop3.check_interval() {
+        writeln!(
+            xml,
+            "{}<checkInterval minutes=\"{}\"/>",
+            INDENT.repeat(depth + 1),
+            interval.as_secs() / 60
+        )
+        .unwrap();
+    }
+
+    writeln!(xml, "{}</pop3>", indent).unwrap();
+}
+
+fn server(xml: &mut String, depth: usize, name: &str, server: &Server) {
+    let indent = INDENT.repeat(depth);
+
+    writeln!(
+        xml,
+        "{}<{} type=\"{}\">",
+        indent,
+        name,
+        server_type(server.server_type())
+    )
+    .unwrap();
+
+    for property in server.properties() {
+        match property {
+            ServerProperty::Hostname(hostname) => element(xml, depth + 1, "hostname", hostname),
+            ServerProperty::Port(port) => element(xml, depth + 1, "port", &port.to_string()),
+            ServerProperty::SocketType(socket_type) => {
+                element(xml, depth + 1, "socketType", security_type(socket_type))
+            }
+            ServerProperty::Authentication(authentication) => element(
+                xml,
+                depth + 1,
+                "authentication",
+                authentication_type(authentication),
+            ),
+            ServerProperty::OwaURL(url) => element(xml, depth + 1, "owaURL", url),
+            ServerProperty::EwsURL(url) => element(xml, depth + 1, "ewsURL", url),
+            // ActiveSync urls are not part of the format, so they are only kept in JSON.
+            ServerProperty::EasURL(_) => {}
+            ServerProperty::UseGlobalPreferredServer(value) => element(
+                xml,
+                depth + 1,
+                "useGlobalPreferredServer",
+                &value.to_string(),
+            ),
+            ServerProperty::Pop3(config) => pop3(xml, depth + 1, config),
+            ServerProperty::Username(username) => element(xml, depth + 1, "username", username),
+            ServerProperty::Password(password) => element(xml, depth + 1, "password", password),
+        }
+    }
+
+    writeln!(xml, "{}</{}>", indent, name).unwrap();
+}
+
+fn documentation(xml: &mut String, depth: usize, documentation: &Documentation) {
+    let indent = INDENT.repeat(depth);
+
+    writeln!(
+        xml,
+        "{}<documentation url=\"{}\">",
+        indent,
+        escape_xml(documentation.url())
+    )
+    .unwrap();
+
+    for description in documentation.properties() {
+        let lang = match description.language() {
+            Some(lang) => format!(" lang=\"{}\"", escape_xml(lang)),
+            None => String::new(),
+        };
+
+        writeln!(
+            xml,
+            "{}<descr{}>{}</descr>",
+            INDENT.repeat(depth + 1),
+            lang,
+            escape_xml(description.description())
+        )
+        .unwrap();
+    }
+
+    writeln!(xml, "{}</documentation>", indent).unwrap();
+}
+
+fn oauth2(xml: &mut String, depth: usize, oauth2: &OAuth2Config) {
+    let indent = INDENT.repeat(depth);
+
+    writeln!(xml, "{}<oAuth2>", indent).unwrap();
+
+    element(xml, depth + 1, "issuer", oauth2.issuer());
+    element(xml, depth + 1, "scope", &oauth2.scope().join(" "));
+    element(xml, depth + 1, "authURL", oauth2.auth_url());
+    element(xml, depth + 1, "tokenURL", oauth2.token_url());
+
+    writeln!(xml, "{}</oAuth2>", indent).unwrap();
+}
+
+/// Writes a config as a `config-v1.1.xml` file, following Mozilla's specification: https://wiki.mozilla.org/Thunderbird:Autoconfiguration:ConfigFileFormat
+pub fn to_string(config: &Config) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+    writeln!(
+        xml,
+        "<clientConfig version=\"{}\">",
+        escape_xml(config.version())
+    )
+    .unwrap();
+
+    let provider = config.email_provider();
+
+    writeln!(
+        xml,
+        "{}<emailProvider id=\"{}\">",
+        INDENT,
+        escape_xml(provider.id())
+    )
+    .unwrap();
+
+    for property in provider.properties() {
+        match property {
+            EmailProviderProperty::Domain(domain) => element(&mut xml, 2, "domain", domain),
+            EmailProviderProperty::DisplayName(name) => element(&mut xml, 2, "displayName", name),
+            EmailProviderProperty::DisplayShortName(name) => {
+                element(&mut xml, 2, "displayShortName", name)
+            }
+            EmailProviderProperty::IncomingServer(incoming) => {
+                server(&mut xml, 2, "incomingServer", incoming)
+            }
+            EmailProviderProperty::OutgoingServer(outgoing) => {
+                server(&mut xml, 2, "outgoingServer", outgoing)
+            }
+            EmailProviderProperty::Documentation(docs) => documentation(&mut xml, 2, docs),
+        }
+    }
+
+    writeln!(xml, "{}</emailProvider>", INDENT).unwrap();
+
+    if let Some(config) = config.oauth2() {
+        oauth2(&mut xml, 1, config);
+    }
+
+    xml.push_str("</clientConfig>\n");
+
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{Config, Server, ServerProperty},
+        discovery::Trust,
+        parse,
+        validate::Diagnostic,
+    };
+
+    /// The elements of Mozilla's config-v1.1.xml specification.
+    const SPEC_ELEMENTS: &[&str] = &[
+        "clientConfig",
+        "emailProvider",
+        "domain",
+        "displayName",
+        "displayShortName",
+        "incomingServer",
+        "outgoingServer",
+        "hostname",
+        "port",
+        "socketType",
+        "username",
+        "password",
+        "authentication",
+        "owaURL",
+        "ewsURL",
+        "useGlobalPreferredServer",
+        "pop3",
+        "leaveMessagesOnServer",
+        "downloadOnBiff",
+        "daysToLeaveMessagesOnServer",
+        "checkInterval",
+        "documentation",
+        "descr",
+        "oAuth2",
+        "issuer",
+        "scope",
+        "authURL",
+        "tokenURL",
+    ];
+
+    #[test]
+    fn test_round_trip() {
+        let mock_config = r#"
+            <?xml version="1.0"?>
+            <clientConfig version="1.1">
+                <emailProvider id="example.com">
+                    <domain>example.com</domain>
+                    <displayName>Example &amp; Co Mail</displayName>
+                    <incomingServer type="pop3">
+                        <hostname>pop.example.com</hostname>
+                        <port>995</port>
+                        <socketType>SSL</socketType>
+                        <username>%EMAILLOCALPART%</username>
+                        <authentication>password-cleartext</authentication>
+                        <pop3>
+                            <leaveMessagesOnServer>true</leaveMessagesOnServer>
+                            <downloadOnBiff>true</downloadOnBiff>
+                            <daysToLeaveMessagesOnServer>14</daysToLeaveMessagesOnServer>
+                            <checkInterval minutes="15"/>
+                        </pop3>
+                    </incomingServer>
+                    <outgoingServer type="smtp">
+                        <hostname>smtp.example.com</hostname>
+                        <port>587</port>
+                        <socketType>STARTTLS</socketType>
+                        <username>%EMAILLOCALPART%</username>
+                        <authentication>OAuth2</authentication>
+                        <useGlobalPreferredServer>true</useGlobalPreferredServer>
+                    </outgoingServer>
+                    <documentation url="http://www.example.com/help/mail?a=1&amp;b=2">
+                        <descr lang="en">Configure Thunderbird 2.0 for IMAP</descr>
+                        <descr>Thunderbird 2.0 mit IMAP konfigurieren</descr>
+                    </documentation>
+                </emailProvider>
+                <oAuth2>
+                    <issuer>accounts.example.com</issuer>
+                    <scope>https://mail.example.com/ https://example.com/contacts</scope>
+                    <authURL>https://accounts.example.com/o/oauth2/auth</authURL>
+                    <tokenURL>https://www.example.com/oauth2/v3/token</tokenURL>
+                </oAuth2>
+            </clientConfig>
+        "#;
+
+        let config = parse::from_bytes(mock_config).unwrap();
+
+        let written = super::to_string(&config);
+
+        assert_eq!(parse::from_bytes(written).unwrap(), config);
+
+        let json = serde_json::to_string(&config).unwrap();
+
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+    }
+
+    #[test]
+    fn test_spec_elements() {
+        let config = Config::builder()
+            .provider("example.com")
+            .domain("example.com")
+            .incoming(
+                Server::exchange("outlook.example.com")
+                    .with_property(ServerProperty::OwaURL(String::from(
+                        "https://outlook.example.com/owa/",
+                    )))
+                    .with_property(ServerProperty::EwsURL(String::from(
+                        "https://outlook.example.com/EWS/Exchange.asmx",
+                    )))
+                    .with_property(ServerProperty::EasURL(String::from(
+                        "https://outlook.example.com/Microsoft-Server-ActiveSync",
+                    ))),
+            )
+            .build()
+            .unwrap();
+
+        let written = super::to_string(&config);
+
+        let elements = written
+            .split('<')
+            .skip(1)
+            .filter(|tag| !tag.starts_with('/') && !tag.starts_with('?'))
+            .map(|tag| {
+                tag.split(|c: char| c == '>' || c == '/' || c.is_whitespace())
+                    .next()
+                    .unwrap()
+            });
+
+        for element in elements {
+            assert!(
+                SPEC_ELEMENTS.contains(&element),
+                "{} is not in the specification",
+                element
+            );
+        }
+
+        assert!(written.contains("<ewsURL>"));
+
+        // The ActiveSync url is still part of the JSON format.
+        let json = serde_json::to_string(&config).unwrap();
+
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+    }
+
+    #[test]
+    fn test_serialize_json() {
+        let mock_config = r#"
+            <clientConfig version="1.1">
+                <emailProvider id="example.com">
+                    <domain>example.com</domain>
+                    <incomingServer type="imap">
+                        <hostname>imap.example.com</hostname>
+                        <socketType>SSL</socketType>
+                    </incomingServer>
+                </emailProvider>
+            </clientConfig>
+        "#;
+
+        let config = parse::from_bytes(mock_config).unwrap();
+
+        let json = serde_json::to_value(&config).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": "1.1",
+                "emailProvider": {
+                    "id": "example.com",
+                    "properties": [
+                        { "domain": "example.com" },
+                        { "incomingServer": {
+                            "type": "imap",
+                            "properties": [
+                                { "hostname": "imap.example.com" },
+                                { "socketType": "SSL" }
+                            ]
+                        } }
+                    ]
+                },
+                "oAuth2": null,
+                "guessed": false,
+                "trust": null,
+                "diagnostics": []
+            })
+        );
+
+        assert_eq!(serde_json::from_value::<Config>(json).unwrap(), config);
+
+        let mut config = config;
+
+        config.set_trust(Trust::MatchingHostnames);
+        config.set_guessed(true);
+        config.set_diagnostics(vec![Diagnostic::MissingPort(String::from(
+            "imap.example.com",
+        ))]);
+
+        let json = serde_json::to_value(&config).unwrap();
+
+        assert_eq!(json["trust"], "matching-hostnames");
+        assert_eq!(
+            json["diagnostics"],
+            serde_json::json!([{ "missingPort": "imap.example.com" }])
+        );
+        assert_eq!(serde_json::from_value::<Config>(json).unwrap(), config);
+    }
+}